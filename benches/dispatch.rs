@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput, measurement::WallTime};
+
+use cult_of_the_bound_var::{assembler::Assembler, testing::NullIO, um::UniversalMachine};
+
+// Counts r1 down to zero; r2 stays 0 and doubles as the id of array 0.
+const ARITHMETIC_LOOP: &str = "
+    STORE_CONST r1, 1000000
+    NAND r3, r2, r2          ; r3 <- -1
+    STORE_CONST r4, loop
+    STORE_CONST r5, done
+loop:
+    ADD r1, r1, r3
+    ADD r6, r5, r2
+    CMOV r6, r4, r1
+    LOAD_PROG r2, r6
+done:
+    HALT
+";
+
+// Allocates and frees a small array on every pass.
+const ALLOC_FREE_LOOP: &str = "
+    STORE_CONST r1, 200000
+    NAND r3, r2, r2
+    STORE_CONST r7, 16
+    STORE_CONST r4, loop
+    STORE_CONST r5, done
+loop:
+    ALLOC r0, r7
+    FREE r0
+    ADD r1, r1, r3
+    ADD r6, r5, r2
+    CMOV r6, r4, r1
+    LOAD_PROG r2, r6
+done:
+    HALT
+";
+
+/// Reports throughput in executed instructions, so criterion prints Melem/s as
+/// millions of instructions per second.
+fn bench_program(group: &mut BenchmarkGroup<WallTime>, name: &str, program: &[u8]) {
+    let mut um = UniversalMachine::new(program, NullIO).unwrap();
+    um.run();
+    group.throughput(Throughput::Elements(um.steps_executed()));
+    group.bench_function(name, |b| b.iter(|| {
+        UniversalMachine::new(program, NullIO).unwrap().run()
+    }));
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    bench_program(&mut group, "arithmetic_loop", &Assembler::assemble(ARITHMETIC_LOOP).unwrap());
+    bench_program(&mut group, "alloc_free", &Assembler::assemble(ALLOC_FREE_LOOP).unwrap());
+    group.finish();
+
+    // Both run the loop to completion under a limit that is never reached; the
+    // difference is the cost of reading the clock every `time_check_interval` steps.
+    let program = Assembler::assemble(ARITHMETIC_LOOP).unwrap();
+    let mut group = c.benchmark_group("limits");
+    let mut um = UniversalMachine::new(&program, NullIO).unwrap();
+    um.run();
+    group.throughput(Throughput::Elements(um.steps_executed()));
+    group.bench_function("step_limit", |b| b.iter(|| {
+        UniversalMachine::builder(NullIO).program(&program).step_limit(u64::MAX).build().unwrap().run()
+    }));
+    group.bench_function("run_for_duration", |b| b.iter(|| {
+        UniversalMachine::new(&program, NullIO).unwrap().run_for_duration(Duration::MAX)
+    }));
+    group.finish();
+
+    let mut group = c.benchmark_group("sandmark");
+    group.sample_size(10);
+    bench_program(&mut group, "full_run", &std::fs::read("data/sandmark.umz").unwrap());
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
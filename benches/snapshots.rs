@@ -0,0 +1,28 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use cult_of_the_bound_var::{testing::NullIO, um::{MachineState, UniversalMachine}};
+
+/// Saves and loads sandmark's state after 10M steps as JSON and as MessagePack.
+fn snapshots(c: &mut Criterion) {
+    let program = std::fs::read("data/sandmark.umz").unwrap();
+    let mut um = UniversalMachine::builder(NullIO).program(&program).step_limit(10_000_000).build().unwrap();
+    um.run();
+    let state = um.save_state();
+
+    let json = serde_json::to_vec(&state).unwrap();
+    let msgpack = state.to_msgpack();
+    assert!(serde_json::from_slice::<MachineState>(&json).unwrap() == state);
+    assert!(MachineState::from_msgpack(&msgpack).unwrap() == state);
+    assert!(msgpack.len() * 2 <= json.len(), "{} bytes of MessagePack against {} of JSON", msgpack.len(), json.len());
+
+    let mut group = c.benchmark_group("snapshots");
+    group.sample_size(10);
+    group.bench_function("json_save", |b| b.iter(|| serde_json::to_vec(&state).unwrap()));
+    group.bench_function("json_load", |b| b.iter(|| serde_json::from_slice::<MachineState>(&json).unwrap()));
+    group.bench_function("msgpack_save", |b| b.iter(|| state.to_msgpack()));
+    group.bench_function("msgpack_load", |b| b.iter(|| MachineState::from_msgpack(&msgpack).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, snapshots);
+criterion_main!(benches);
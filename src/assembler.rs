@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use crate::um::{plate_to_word, Command, Plate, RegId, UmError, UniversalMachine};
+
+/// Turns UM assembly into a big-endian program binary.
+///
+/// Each line holds at most one instruction in the syntax produced by `Command`'s
+/// `Display` impl, optionally preceded by a label (`LABEL loop:` or just `loop:`).
+/// `DATA value` emits a raw platter. Anything after `;` or `#` is a comment.
+/// Wherever a constant is expected (`STORE_CONST`, `DATA`) a label may be used
+/// instead and resolves to its offset in array 0. Mnemonics are case-insensitive.
+pub struct Assembler;
+
+impl Assembler {
+    pub fn assemble(source: &str) -> Result<Vec<u8>, UmError> {
+        let plates = Assembler::assemble_plates(source)?.0;
+        Ok(plates.iter().flat_map(|&p| plate_to_word(p)).collect())
+    }
+
+    /// Same as `assemble`, also returning the offsets of all labels.
+    pub fn assemble_with_symbols(source: &str) -> Result<(Vec<u8>, SymbolTable), UmError> {
+        let (plates, labels) = Assembler::assemble_plates(source)?;
+        Ok((plates.iter().flat_map(|&p| plate_to_word(p)).collect(), SymbolTable(labels)))
+    }
+
+    pub(crate) fn assemble_plates(source: &str) -> Result<(Vec<Plate>, HashMap<String, usize>), UmError> {
+        let mut labels = HashMap::new();
+        let mut instructions = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line_no = i + 1;
+            let mut text = strip_comment(line).trim();
+            if let Some((label, rest)) = split_label(text) {
+                if !is_identifier(label) {
+                    return Err(error(line_no, format!("invalid label name `{}`", label)));
+                }
+                if labels.insert(label.to_string(), instructions.len()).is_some() {
+                    return Err(error(line_no, format!("label `{}` is defined twice", label)));
+                }
+                text = rest.trim();
+            }
+            if !text.is_empty() {
+                instructions.push((line_no, text));
+            }
+        }
+        let plates = instructions.into_iter()
+            .map(|(line_no, text)| parse_instruction(text, &labels)
+                 .map_err(|message| error(line_no, message)))
+            .collect::<Result<Vec<Plate>, UmError>>()?;
+        Ok((plates, labels))
+    }
+}
+
+/// Label names of an assembled program and their offsets in array 0.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymbolTable(pub HashMap<String, usize>);
+
+impl SymbolTable {
+    pub fn lookup(&self, name: &str) -> Option<usize> {
+        self.0.get(name).copied()
+    }
+
+    /// Points `machine` at the label `name`, failing with `UmError::UnknownSymbol` if
+    /// there is no such label.
+    pub fn set_ip_by_symbol(&self, machine: &mut UniversalMachine, name: &str) -> Result<(), UmError> {
+        let offset = self.lookup(name).ok_or_else(|| UmError::UnknownSymbol { name: name.to_string() })?;
+        machine.set_ip(offset)
+    }
+}
+
+fn error(line: usize, message: String) -> UmError {
+    UmError::AssemblyFailed { line, message }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find([';', '#']) {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (head, rest) = text.split_once(':')?;
+    let head = head.trim();
+    let label = match head.split_once(char::is_whitespace) {
+        Some((keyword, name)) if keyword.eq_ignore_ascii_case("LABEL") => name.trim(),
+        _ => head,
+    };
+    Some((label, rest))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_register(operand: &str) -> Result<RegId, String> {
+    operand.strip_prefix(['r', 'R'])
+        .and_then(|n| n.parse::<RegId>().ok())
+        .filter(|&n| n < 8)
+        .ok_or_else(|| format!("expected a register r0-r7, found `{}`", operand))
+}
+
+fn parse_value(operand: &str, labels: &HashMap<String, usize>) -> Result<Plate, String> {
+    let parsed = match operand.strip_prefix("0x").or_else(|| operand.strip_prefix("0X")) {
+        Some(hex) => Plate::from_str_radix(hex, 16).ok(),
+        None if operand.starts_with(|c: char| c.is_ascii_digit()) => operand.parse().ok(),
+        None => match labels.get(operand) {
+            Some(&offset) => Plate::try_from(offset).ok(),
+            None => return Err(format!("undefined label `{}`", operand)),
+        },
+    };
+    parsed.ok_or_else(|| format!("invalid value `{}`", operand))
+}
+
+fn parse_instruction(text: &str, labels: &HashMap<String, usize>) -> Result<Plate, String> {
+    let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+        None => (text, ""),
+    };
+    let operands: Vec<&str> = if operands.is_empty() {
+        Vec::new()
+    } else {
+        operands.split(',').map(str::trim).collect()
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let expected = match mnemonic.as_str() {
+        "HALT" => 0,
+        "FREE" | "OUT" | "IN" | "DATA" => 1,
+        "ALLOC" | "LOAD_PROG" | "STORE_CONST" => 2,
+        "CMOV" | "LOAD" | "STORE" | "ADD" | "MUL" | "DIV" | "NAND" => 3,
+        _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+    };
+    if operands.len() != expected {
+        return Err(format!("`{}` takes {} operand(s), found {}", mnemonic, expected, operands.len()));
+    }
+    if mnemonic == "DATA" {
+        return parse_value(operands[0], labels);
+    }
+    if mnemonic == "STORE_CONST" {
+        let dst = parse_register(operands[0])?;
+        let val = parse_value(operands[1], labels)?;
+        if val >= 1 << 25 {
+            return Err(format!("constant {:#x} does not fit in 25 bits", val));
+        }
+        return Ok(Command::StoreConst { dst, val }.encode());
+    }
+    let regs = operands.iter()
+        .map(|o| parse_register(o))
+        .collect::<Result<Vec<RegId>, String>>()?;
+    let command = match (mnemonic.as_str(), regs.as_slice()) {
+        ("CMOV", &[dst, src, cnd]) => Command::CondMove { dst, src, cnd },
+        ("LOAD", &[dst, arr, offset]) => Command::ArrLoad { dst, arr, offset },
+        ("STORE", &[arr, offset, src]) => Command::ArrStore { src, arr, offset },
+        ("ADD", &[dst, op1, op2]) => Command::Add { dst, op1, op2 },
+        ("MUL", &[dst, op1, op2]) => Command::Mul { dst, op1, op2 },
+        ("DIV", &[dst, op1, op2]) => Command::Div { dst, op1, op2 },
+        ("NAND", &[dst, op1, op2]) => Command::NotAnd { dst, op1, op2 },
+        ("HALT", &[]) => Command::Halt,
+        ("ALLOC", &[dst, size]) => Command::Alloc { dst, size },
+        ("FREE", &[arr]) => Command::Free { arr },
+        ("OUT", &[src]) => Command::Output { src },
+        ("IN", &[dst]) => Command::Input { dst },
+        ("LOAD_PROG", &[arr, offset]) => Command::LoadProg { arr, offset },
+        _ => unreachable!(),
+    };
+    Ok(command.encode())
+}
+
+#[test]
+fn assembles_loop() {
+    use crate::{testing::RecordingIO, um::UniversalMachine};
+
+    let source = "
+            STORE_CONST r0, 3       ; counter
+            STORE_CONST r2, 0x2A    ; '*'
+            NAND r5, r4, r4         # r5 = -1
+        LABEL loop:
+            OUT r2
+            ADD r0, r0, r5
+            STORE_CONST r6, done
+            STORE_CONST r7, loop
+            CMOV r6, r7, r0
+            LOAD_PROG r4, r6
+        done: HALT
+    ";
+    let program = Assembler::assemble(source).unwrap();
+    let mut io = RecordingIO::new(&[]);
+    UniversalMachine::new(&program, &mut io).unwrap().run();
+    assert_eq!(io.output_bytes(), b"***");
+}
+
+#[test]
+fn jumps_to_symbols() {
+    use crate::{testing::RecordingIO, um::UniversalMachine};
+
+    let source = "
+            STORE_CONST r0, 0x41
+        main_loop:
+            OUT r0
+            HALT
+    ";
+    let (program, symbols) = Assembler::assemble_with_symbols(source).unwrap();
+    assert_eq!(symbols.lookup("main_loop"), Some(1));
+    let mut io = RecordingIO::new(&[]);
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    assert_eq!(symbols.set_ip_by_symbol(&mut um, "missing"),
+               Err(UmError::UnknownSymbol { name: "missing".to_string() }));
+    symbols.set_ip_by_symbol(&mut um, "main_loop").unwrap();
+    um.run();
+    drop(um);
+    assert_eq!(io.output_bytes(), b"\0");
+}
+
+#[test]
+fn reports_line_numbers() {
+    let err = |source| match Assembler::assemble(source) {
+        Err(UmError::AssemblyFailed { line, message }) => (line, message),
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(err("HALT\nJMP r0"), (2, "unknown instruction `JMP`".to_string()));
+    assert_eq!(err("STORE_CONST r1, nowhere"), (1, "undefined label `nowhere`".to_string()));
+    assert_eq!(err("ADD r0, r8, r1"), (1, "expected a register r0-r7, found `r8`".to_string()));
+    assert_eq!(err("a:\na: HALT"), (2, "label `a` is defined twice".to_string()));
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn assembles_display_output(cmd in crate::um::any_command()) {
+        let (plates, _) = Assembler::assemble_plates(&cmd.to_string()).unwrap();
+        proptest::prop_assert_eq!(plates, vec![cmd.encode()]);
+    }
+}
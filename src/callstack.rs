@@ -0,0 +1,109 @@
+use alloc::vec::Vec;
+
+use crate::{hooks::Hooks, um::{Command, Plate}};
+
+/// Reconstructs a logical call stack from jumps within array 0, for backtraces. Attach
+/// it with `UmBuilder::hooks(&mut stack)`.
+///
+/// The machine has no call instruction, so this is a heuristic: a `LoadProg` within
+/// array 0 at `ip` counts as a call when some register was last set by `STORE_CONST` to
+/// `ip + 1`, the return address, and a jump to a recorded return address counts as a
+/// return, discarding any frames above it. Replacing array 0 clears the stack.
+#[derive(Debug, Default, Clone)]
+pub struct CallStack {
+    frames: Vec<usize>,
+    constants: [Option<Plate>; 8],
+    pending_call: Option<usize>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    pub fn current_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Return addresses of the active calls, innermost last.
+    pub fn frames(&self) -> &[usize] {
+        &self.frames
+    }
+}
+
+impl Hooks for CallStack {
+    fn on_step(&mut self, ip: usize, command: &Command) {
+        let dst = match *command {
+            Command::StoreConst { dst, val } => {
+                self.constants[dst as usize] = Some(val);
+                return;
+            },
+            Command::LoadProg { .. } => {
+                let return_address = ip + 1;
+                self.pending_call = self.constants.iter()
+                    .any(|&c| c.and_then(|c| usize::try_from(c).ok()) == Some(return_address))
+                    .then_some(return_address);
+                return;
+            },
+            Command::CondMove { dst, .. } | Command::ArrLoad { dst, .. } | Command::Add { dst, .. }
+            | Command::Mul { dst, .. } | Command::Div { dst, .. } | Command::NotAnd { dst, .. }
+            | Command::Alloc { dst, .. } | Command::Input { dst } => dst,
+            _ => return,
+        };
+        self.constants[dst as usize] = None;
+    }
+
+    fn on_load_prog(&mut self, src_arr: Plate, new_ip: usize) {
+        let call = self.pending_call.take();
+        if src_arr != 0 {
+            self.frames.clear();
+        } else if let Some(depth) = self.frames.iter().rposition(|&f| f == new_ip) {
+            self.frames.truncate(depth);
+        } else if let Some(return_address) = call {
+            self.frames.push(return_address);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn tracks_calls_and_returns() {
+    use crate::{assembler::Assembler, testing::NullIO, um::UniversalMachine};
+
+    // `main` calls `outer`, which calls `inner`; `inner` jumps over a data word without
+    // calling anything and then either halts or returns.
+    let source = |inner_end: &str| format!("
+        STORE_CONST r7, ret_main
+        STORE_CONST r6, outer
+        LOAD_PROG r0, r6
+    ret_main:
+        HALT
+    outer:
+        STORE_CONST r5, inner
+        STORE_CONST r4, ret_outer
+        LOAD_PROG r0, r5
+    ret_outer:
+        LOAD_PROG r0, r7
+    inner:
+        STORE_CONST r2, inner_end
+        LOAD_PROG r0, r2
+        DATA 0
+    inner_end:
+        {}
+    ", inner_end);
+    let run = |inner_end| {
+        let (program, symbols) = Assembler::assemble_with_symbols(&source(inner_end)).unwrap();
+        let mut stack = CallStack::new();
+        let mut um = UniversalMachine::builder(NullIO).program(&program).hooks(&mut stack).build().unwrap();
+        um.run();
+        drop(um);
+        (stack, symbols)
+    };
+
+    let (stack, symbols) = run("HALT");
+    assert_eq!(stack.current_depth(), 2);
+    assert_eq!(stack.frames(), [symbols.lookup("ret_main").unwrap(), symbols.lookup("ret_outer").unwrap()]);
+
+    let (stack, _) = run("LOAD_PROG r0, r4");
+    assert_eq!(stack.current_depth(), 0);
+}
@@ -0,0 +1,60 @@
+use core::{cmp::Reverse, fmt, ops::Index};
+
+use alloc::vec::Vec;
+
+use crate::um::Command;
+
+pub(crate) const OPCODE_NAMES: [&str; 14] = [
+    "CMOV", "LOAD", "STORE", "ADD", "MUL", "DIV", "NAND",
+    "HALT", "ALLOC", "FREE", "OUT", "IN", "LOAD_PROG", "STORE_CONST",
+];
+
+/// Number of executed instructions per opcode, indexed by opcode number.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InstructionCounter(pub [u64; 14]);
+
+impl InstructionCounter {
+    pub fn record(&mut self, command: &Command) {
+        if let Some(opcode) = command.opcode() {
+            self.0[opcode as usize] += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+}
+
+impl Index<u8> for InstructionCounter {
+    type Output = u64;
+
+    fn index(&self, opcode: u8) -> &Self::Output {
+        &self.0[opcode as usize]
+    }
+}
+
+impl fmt::Display for InstructionCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rows: Vec<(&str, u64)> = OPCODE_NAMES.iter().copied().zip(self.0).collect();
+        rows.sort_by_key(|&(_, count)| Reverse(count));
+        for (name, count) in rows {
+            writeln!(f, "{:<12} {:>14}", name, count)?;
+        }
+        write!(f, "{:<12} {:>14}", "TOTAL", self.total())
+    }
+}
+
+#[test]
+fn counts_sorted_by_frequency() {
+    let mut counter = InstructionCounter::default();
+    counter.record(&Command::Halt);
+    counter.record(&Command::Add { dst: 0, op1: 1, op2: 2 });
+    counter.record(&Command::Add { dst: 0, op1: 1, op2: 2 });
+    counter.record(&Command::Invalid(0xF0000000));
+    assert_eq!(counter[3], 2);
+    assert_eq!(counter.total(), 3);
+    let table = counter.to_string();
+    let mut lines = table.lines();
+    assert!(lines.next().unwrap().starts_with("ADD"));
+    assert!(lines.next().unwrap().starts_with("HALT"));
+}
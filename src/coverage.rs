@@ -0,0 +1,79 @@
+use alloc::vec::Vec;
+
+use crate::um::UniversalMachine;
+
+/// Records which offsets of array 0 have been executed. When a `LoadProg` installs a
+/// longer program the tracked range grows; offsets already marked stay marked.
+#[derive(Debug, Default, Clone)]
+pub struct CoverageTracker {
+    executed: Vec<bool>,
+}
+
+impl CoverageTracker {
+    pub fn new(machine: &UniversalMachine) -> Self {
+        let mut tracker = CoverageTracker::default();
+        tracker.extend_to(machine);
+        tracker
+    }
+
+    /// Marks the instruction at `ip` and executes it.
+    pub fn step(&mut self, machine: &mut UniversalMachine) -> bool {
+        if machine.is_halted {
+            return false;
+        }
+        self.extend_to(machine);
+        if let Some(executed) = self.executed.get_mut(machine.ip) {
+            *executed = true;
+        }
+        machine.step()
+    }
+
+    pub fn run(&mut self, machine: &mut UniversalMachine) {
+        while self.step(machine) {}
+    }
+
+    pub fn executed_count(&self) -> usize {
+        self.executed.iter().filter(|&&e| e).count()
+    }
+
+    pub fn total_instructions(&self) -> usize {
+        self.executed.len()
+    }
+
+    pub fn coverage_fraction(&self) -> f64 {
+        if self.executed.is_empty() {
+            return 0.0;
+        }
+        self.executed_count() as f64 / self.total_instructions() as f64
+    }
+
+    pub fn uncovered_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.executed.iter()
+            .enumerate()
+            .filter(|(_, &e)| !e)
+            .map(|(offset, _)| offset)
+    }
+
+    fn extend_to(&mut self, machine: &UniversalMachine) {
+        let len = machine.memory_map().array_len(0).unwrap_or(0);
+        if len > self.executed.len() {
+            self.executed.resize(len, false);
+        }
+    }
+}
+
+#[test]
+fn tracks_executed_offsets() {
+    use crate::{testing::NullIO, um::program_bytes};
+
+    // r0 <- 3; jump to r0 in array 0; <skipped>; halt
+    let program = program_bytes(&[0xD0000003, 0xC0000008, 0xD2000001, 0x70000000]);
+    let mut io = NullIO;
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    let mut coverage = CoverageTracker::new(&um);
+    coverage.run(&mut um);
+    assert_eq!(coverage.total_instructions(), 4);
+    assert_eq!(coverage.executed_count(), 3);
+    assert_eq!(coverage.coverage_fraction(), 0.75);
+    assert_eq!(coverage.uncovered_offsets().collect::<Vec<_>>(), vec![2]);
+}
@@ -0,0 +1,75 @@
+use std::hash::{Hash, Hasher};
+
+use rustc_hash::{FxHashMap, FxHasher};
+
+use crate::um::UniversalMachine;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunResult {
+    Halted,
+    /// The machine was in the same state at both steps, counted from the first
+    /// instruction executed through the detector.
+    InfiniteLoop { first_seen_at_step: u64, repeated_at_step: u64 },
+}
+
+/// Detects infinite loops by hashing `ip`, the registers and array 0 every `interval`
+/// steps and looking for a hash that was seen before.
+///
+/// The check is probabilistic: a hash collision reports a loop that is not there.
+/// Arrays other than 0 and pending input are not part of the snapshot either, so a
+/// program that only makes progress through them is reported as looping too.
+pub struct CycleDetector {
+    interval: u64,
+    step: u64,
+    seen: FxHashMap<u64, u64>,
+}
+
+impl CycleDetector {
+    pub fn new(interval: u64) -> Self {
+        assert!(interval > 0, "snapshot interval must be positive");
+        CycleDetector {
+            interval,
+            step: 0,
+            seen: FxHashMap::default(),
+        }
+    }
+
+    /// Runs until the machine halts or a repeated snapshot is found.
+    pub fn run(&mut self, machine: &mut UniversalMachine) -> RunResult {
+        loop {
+            if self.step.is_multiple_of(self.interval) {
+                if let Some(first_seen_at_step) = self.seen.insert(snapshot_hash(machine), self.step) {
+                    return RunResult::InfiniteLoop { first_seen_at_step, repeated_at_step: self.step };
+                }
+            }
+            if !machine.step() {
+                return RunResult::Halted;
+            }
+            self.step += 1;
+        }
+    }
+}
+
+fn snapshot_hash(machine: &UniversalMachine) -> u64 {
+    let mut hasher = FxHasher::default();
+    machine.ip.hash(&mut hasher);
+    machine.registers.regs.hash(&mut hasher);
+    machine.memory_map().array_slice(0).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn reports_repeated_state() {
+    use crate::{testing::NullIO, um::program_bytes};
+
+    // r1 <- 3; r1 <- r1 + r2; jump to r0 in array 0
+    let program = program_bytes(&[0xD2000003, 0x3000004A, 0xC0000000]);
+    let mut io = NullIO;
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    let result = CycleDetector::new(2).run(&mut um);
+    assert_eq!(result, RunResult::InfiniteLoop { first_seen_at_step: 2, repeated_at_step: 8 });
+
+    let program = program_bytes(&[0xD2000003, 0x70000000]);
+    let mut um = UniversalMachine::new(&program, NullIO).unwrap();
+    assert_eq!(CycleDetector::new(1).run(&mut um), RunResult::Halted);
+}
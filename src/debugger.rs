@@ -0,0 +1,123 @@
+use alloc::collections::BTreeSet;
+
+use crate::um::{Command, Plate, UniversalMachine};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebuggerStop {
+    Breakpoint(usize),
+    /// An `ArrStore` just wrote to a watched location.
+    Watchpoint { id: Plate, offset: usize, old_value: Plate, new_value: Plate },
+    Halted,
+}
+
+pub struct Debugger<'a> {
+    pub machine: UniversalMachine<'a>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<(Plate, usize)>,
+}
+
+impl <'a> Debugger<'a> {
+    pub fn new(machine: UniversalMachine<'a>) -> Self {
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stops execution after any `ArrStore` to `offset` of array `id`, even one that
+    /// writes the value already stored there.
+    pub fn add_watchpoint(&mut self, id: Plate, offset: usize) {
+        self.watchpoints.insert((id, offset));
+    }
+
+    pub fn remove_watchpoint(&mut self, id: Plate, offset: usize) {
+        self.watchpoints.remove(&(id, offset));
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (Plate, usize)> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// The location the next instruction stores into, if it is a watched `ArrStore`.
+    fn watched_store(&self) -> Option<(Plate, usize)> {
+        if self.watchpoints.is_empty() || self.machine.is_halted {
+            return None;
+        }
+        let command = Command::decode(self.machine.read_array(0, self.machine.ip)?);
+        let Command::ArrStore { arr, offset, .. } = command else {
+            return None;
+        };
+        let target = (self.machine.registers[arr], self.machine.registers[offset] as usize);
+        self.watchpoints.contains(&target).then_some(target)
+    }
+
+    /// Steps the machine until `ip` lands on a breakpoint in array 0, a watched location
+    /// is written or the machine halts. At least one instruction is executed, so calling
+    /// this again while stopped continues past the stop.
+    pub fn run_to_breakpoint(&mut self) -> DebuggerStop {
+        loop {
+            let watched = self.watched_store();
+            let old_value = watched.and_then(|(id, offset)| self.machine.read_array(id, offset));
+            if !self.machine.step() {
+                return DebuggerStop::Halted;
+            }
+            if let (Some((id, offset)), Some(old_value)) = (watched, old_value) {
+                let new_value = self.machine.read_array(id, offset).unwrap();
+                return DebuggerStop::Watchpoint { id, offset, old_value, new_value };
+            }
+            if self.breakpoints.contains(&self.machine.ip) {
+                return DebuggerStop::Breakpoint(self.machine.ip);
+            }
+        }
+    }
+}
+
+#[test]
+fn stops_on_breakpoints() {
+    use crate::{testing::NullIO, um::program_bytes};
+
+    let program = program_bytes(&[0xD0000001, 0xD2000002, 0x30000081, 0x70000000]);
+    let mut io = NullIO;
+    let mut debugger = Debugger::new(UniversalMachine::new(&program, &mut io).unwrap());
+    debugger.add_breakpoint(2);
+    debugger.add_breakpoint(3);
+    assert_eq!(debugger.run_to_breakpoint(), DebuggerStop::Breakpoint(2));
+    assert_eq!(debugger.machine.registers.regs[2], 0);
+    debugger.remove_breakpoint(3);
+    assert_eq!(debugger.run_to_breakpoint(), DebuggerStop::Halted);
+    assert_eq!(debugger.machine.registers.regs[2], 3);
+}
+
+// Expects ids in allocation order.
+#[cfg(not(feature = "randomize-ids"))]
+#[test]
+fn stops_on_watchpoints() {
+    use crate::{testing::NullIO, um::program_bytes};
+
+    // r0 <- 2; r1 <- new[r0]; r2 <- 1; r3 <- 7; r1[r2] <- r3; r1[r5] <- r3; halt
+    let program = program_bytes(&[0xD0000002, 0x80000008, 0xD4000001, 0xD6000007, 0x20000053,
+                                  0x2000006B, 0x70000000]);
+    let mut debugger = Debugger::new(UniversalMachine::new(&program, NullIO).unwrap());
+    debugger.add_watchpoint(1, 1);
+    assert_eq!(debugger.run_to_breakpoint(),
+               DebuggerStop::Watchpoint { id: 1, offset: 1, old_value: 0, new_value: 7 });
+    assert_eq!(debugger.machine.ip, 5);
+    assert_eq!(debugger.run_to_breakpoint(), DebuggerStop::Halted);
+}
@@ -0,0 +1,140 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::um::{MachineState, Plate, RegId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub reg: RegId,
+    pub before: Plate,
+    pub after: Plate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellChange {
+    pub id: Plate,
+    pub offset: usize,
+    pub before: Plate,
+    pub after: Plate,
+}
+
+/// Everything that differs between two snapshots. An array whose length changed, for
+/// instance one freed and allocated again in between, is listed as both freed and
+/// allocated rather than cell by cell.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<RegisterChange>,
+    pub ip: Option<(usize, usize)>,
+    pub is_halted: Option<(bool, bool)>,
+    pub cells: Vec<CellChange>,
+    /// Arrays live in the second snapshot only, with their full contents.
+    pub allocated: Vec<(Plate, Vec<Plate>)>,
+    pub freed: Vec<Plate>,
+    /// Length of the array table in the second snapshot.
+    pub array_slots: usize,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.ip.is_none() && self.is_halted.is_none() && self.cells.is_empty()
+            && self.allocated.is_empty() && self.freed.is_empty()
+    }
+}
+
+pub fn compute_diff(state_a: &MachineState, state_b: &MachineState) -> StateDiff {
+    let mut diff = StateDiff { array_slots: state_b.arrays.len(), ..StateDiff::default() };
+    for (reg, (&before, &after)) in state_a.registers.regs.iter().zip(&state_b.registers.regs).enumerate() {
+        if before != after {
+            diff.registers.push(RegisterChange { reg: reg as RegId, before, after });
+        }
+    }
+    diff.ip = (state_a.ip != state_b.ip).then_some((state_a.ip, state_b.ip));
+    diff.is_halted = (state_a.is_halted != state_b.is_halted).then_some((state_a.is_halted, state_b.is_halted));
+
+    let slots = state_a.arrays.len().max(state_b.arrays.len());
+    for id in 0..slots {
+        let a = state_a.arrays.get(id).and_then(|a| a.as_deref());
+        let b = state_b.arrays.get(id).and_then(|b| b.as_deref());
+        match (a, b) {
+            (Some(a), Some(b)) if a.len() == b.len() => {
+                let changes = a.iter().zip(b).enumerate()
+                    .filter(|(_, (before, after))| before != after)
+                    .map(|(offset, (&before, &after))| CellChange { id: id as Plate, offset, before, after });
+                diff.cells.extend(changes);
+            },
+            _ => {
+                if a.is_some() {
+                    diff.freed.push(id as Plate);
+                }
+                if let Some(b) = b {
+                    diff.allocated.push((id as Plate, b.to_vec()));
+                }
+            },
+        }
+    }
+    diff
+}
+
+/// Turns the first snapshot `compute_diff` was given into the second.
+pub fn apply_diff(state: &mut MachineState, diff: &StateDiff) {
+    for change in &diff.registers {
+        state.registers[change.reg] = change.after;
+    }
+    if let Some((_, ip)) = diff.ip {
+        state.ip = ip;
+    }
+    if let Some((_, is_halted)) = diff.is_halted {
+        state.is_halted = is_halted;
+    }
+    for &id in &diff.freed {
+        state.arrays[id as usize] = None;
+    }
+    state.arrays.resize(diff.array_slots, None);
+    for (id, contents) in &diff.allocated {
+        state.arrays[*id as usize] = Some(Arc::from(contents.as_slice()));
+    }
+    for change in &diff.cells {
+        let array = state.arrays[change.id as usize].as_mut().expect("diff applied to a different snapshot");
+        Arc::make_mut(array)[change.offset] = change.after;
+    }
+}
+
+// Expects ids in allocation order.
+#[cfg(not(feature = "randomize-ids"))]
+#[test]
+fn apply_diff_reconstructs_later_state() {
+    use crate::{testing::NullIO, um::{program_bytes, UniversalMachine}};
+
+    // r0 <- 3; r1 <- new[r0]; r2 <- new[r0]; r3 <- 9; r1[r5] <- r3; free r2; halt
+    let program = program_bytes(&[0xD0000003, 0x80000008, 0x80000010, 0xD6000009, 0x2000006B, 0x90000002,
+                                  0x70000000]);
+    let mut um = UniversalMachine::new(&program, NullIO).unwrap();
+    let initial = um.save_state();
+    um.step();
+    um.step();
+    let before = um.save_state();
+    um.run();
+    let after = um.save_state();
+
+    let diff = compute_diff(&before, &after);
+    assert_eq!(diff.registers, [
+        RegisterChange { reg: 2, before: 0, after: 2 },
+        RegisterChange { reg: 3, before: 0, after: 9 },
+    ]);
+    assert_eq!(diff.ip, Some((2, 7)));
+    assert_eq!(diff.is_halted, Some((false, true)));
+    assert_eq!(diff.cells, [CellChange { id: 1, offset: 0, before: 0, after: 9 }]);
+    assert!(diff.allocated.is_empty());
+    assert!(diff.freed.is_empty());
+
+    let mut rebuilt = before.clone();
+    apply_diff(&mut rebuilt, &diff);
+    assert!(compute_diff(&rebuilt, &after).is_empty());
+
+    assert_eq!(compute_diff(&initial, &after).allocated, [(1, vec![9, 0, 0])]);
+    let diff = compute_diff(&after, &initial);
+    assert_eq!(diff.freed, [1]);
+    let mut rebuilt = after.clone();
+    apply_diff(&mut rebuilt, &diff);
+    assert!(compute_diff(&rebuilt, &initial).is_empty());
+    assert_eq!(rebuilt.arrays.len(), 1);
+}
@@ -0,0 +1,43 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::um::{parse_program, Command, UmError};
+
+pub struct Disassembler;
+
+impl Disassembler {
+    /// Decodes every platter of `program`, returning its byte offset, the decoded
+    /// command and a printable line such as `[0x0000]  CMOV r3, r1, r2`.
+    /// Data platters that carry no valid opcode are reported as `Command::Invalid`.
+    pub fn disassemble(program: &[u8]) -> Result<Vec<(usize, Command, String)>, UmError> {
+        let plates = parse_program(program)?;
+        Ok(plates.into_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let offset = i * 4;
+                let command = Command::decode(p);
+                let text = format!("[{:#06x}]  {}", offset, command);
+                (offset, command, text)
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn disassemble_words() {
+    let program = [
+        0x00, 0x00, 0x00, 0xCA,
+        0x70, 0x00, 0x00, 0x00,
+        0xF0, 0x00, 0x00, 0x01,
+    ];
+    let lines: Vec<String> = Disassembler::disassemble(&program).unwrap()
+        .into_iter()
+        .map(|(_, _, text)| text)
+        .collect();
+    assert_eq!(lines, vec![
+        "[0x0000]  CMOV r3, r1, r2",
+        "[0x0004]  HALT",
+        "[0x0008]  DATA 0xf0000001",
+    ]);
+    assert_eq!(Disassembler::disassemble(&program[..5]),
+               Err(UmError::MalformedProgram { byte_offset: 4 }));
+}
@@ -0,0 +1,169 @@
+use crate::um::{Command, Plate};
+
+/// Callbacks for machine events, all no-ops by default. Attach an implementation with
+/// `UmBuilder::hooks`; `on_step` runs before the instruction is performed. Array ids
+/// are the ones the program holds in its registers.
+pub trait Hooks: Send {
+    fn on_step(&mut self, _ip: usize, _command: &Command) {}
+    fn on_alloc(&mut self, _id: Plate, _size: usize) {}
+    /// Only called for arrays that were actually allocated.
+    fn on_free(&mut self, _id: Plate) {}
+    fn on_load_prog(&mut self, _src_arr: Plate, _new_ip: usize) {}
+    /// Called when the machine halts, whether on `Halt` or on an error.
+    fn on_halt(&mut self) {}
+}
+
+/// Ignores every event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopHooks;
+
+impl Hooks for NoopHooks {}
+
+impl <T: Hooks + ?Sized> Hooks for &mut T {
+    fn on_step(&mut self, ip: usize, command: &Command) {
+        (**self).on_step(ip, command)
+    }
+
+    fn on_alloc(&mut self, id: Plate, size: usize) {
+        (**self).on_alloc(id, size)
+    }
+
+    fn on_free(&mut self, id: Plate) {
+        (**self).on_free(id)
+    }
+
+    fn on_load_prog(&mut self, src_arr: Plate, new_ip: usize) {
+        (**self).on_load_prog(src_arr, new_ip)
+    }
+
+    fn on_halt(&mut self) {
+        (**self).on_halt()
+    }
+}
+
+// Expects ids in allocation order.
+#[cfg(not(feature = "randomize-ids"))]
+#[test]
+fn receives_machine_events() {
+    use crate::{testing::NullIO, um::{program_bytes, UniversalMachine}};
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Hooks for Events {
+        fn on_alloc(&mut self, id: Plate, size: usize) {
+            self.0.push(format!("alloc {} {}", id, size));
+        }
+
+        fn on_free(&mut self, id: Plate) {
+            self.0.push(format!("free {}", id));
+        }
+
+        fn on_load_prog(&mut self, src_arr: Plate, new_ip: usize) {
+            self.0.push(format!("load_prog {} {}", src_arr, new_ip));
+        }
+
+        fn on_halt(&mut self) {
+            self.0.push("halt".to_string());
+        }
+    }
+
+    // r0 <- 2; r1 <- new[r0]; free r1; r2 <- 5; jump to r2 in array 0; halt
+    let program = program_bytes(&[0xD0000002, 0x80000008, 0x90000001, 0xD4000005, 0xC000003A, 0x70000000]);
+    let mut events = Events::default();
+    let mut um = UniversalMachine::builder(NullIO)
+        .program(&program)
+        .hooks(&mut events)
+        .build()
+        .unwrap();
+    um.run();
+    drop(um);
+    assert_eq!(events.0, ["alloc 1 2", "free 1", "load_prog 0 5", "halt"]);
+}
+
+#[test]
+fn run_with_hooks_stacks_on_attached_hooks() {
+    use crate::{testing::{test_program, NullIO}, um::{RunOutcome, UniversalMachine}};
+
+    #[derive(Default)]
+    struct Steps {
+        ips: Vec<usize>,
+        halted: bool,
+    }
+
+    impl Hooks for Steps {
+        fn on_step(&mut self, ip: usize, _command: &Command) {
+            self.ips.push(ip);
+        }
+
+        fn on_halt(&mut self) {
+            self.halted = true;
+        }
+    }
+
+    /// Counts allocations and hands every event on to `inner`.
+    struct CountAllocs<H> {
+        allocs: usize,
+        inner: H,
+    }
+
+    impl <H: Hooks> Hooks for CountAllocs<H> {
+        fn on_step(&mut self, ip: usize, command: &Command) {
+            self.inner.on_step(ip, command)
+        }
+
+        fn on_alloc(&mut self, id: Plate, size: usize) {
+            self.allocs += 1;
+            self.inner.on_alloc(id, size)
+        }
+
+        fn on_halt(&mut self) {
+            self.inner.on_halt()
+        }
+    }
+
+    let program = test_program![StoreConst r0 = 2; Alloc r1, r0; Alloc r2, r0; Halt];
+    let mut attached = Steps::default();
+    let mut um = UniversalMachine::builder(NullIO)
+        .program(&program)
+        .hooks(&mut attached)
+        .build()
+        .unwrap();
+    let mut hooks = CountAllocs { allocs: 0, inner: Steps::default() };
+    assert!(matches!(um.run_with_hooks(&mut hooks, Some(2)), RunOutcome::StepLimitReached(2)));
+    assert_eq!(hooks.allocs, 1);
+    assert!(matches!(um.run_with_hooks(&mut hooks, None), RunOutcome::Halted));
+    assert_eq!(hooks.allocs, 2);
+    assert_eq!(hooks.inner.ips, [0, 1, 2, 3]);
+    assert!(hooks.inner.halted);
+    drop(um);
+    assert_eq!(attached.ips, [0, 1, 2, 3]);
+    assert!(attached.halted);
+}
+
+#[cfg(feature = "randomize-ids")]
+#[test]
+fn reports_ids_seen_by_the_program() {
+    use crate::{testing::{test_program, NullIO}, um::UniversalMachine};
+
+    #[derive(Default)]
+    struct Ids(Vec<Plate>);
+
+    impl Hooks for Ids {
+        fn on_alloc(&mut self, id: Plate, _size: usize) {
+            self.0.push(id);
+        }
+
+        fn on_free(&mut self, id: Plate) {
+            self.0.push(id);
+        }
+    }
+
+    let program = test_program![StoreConst r0 = 2; Alloc r1, r0; Free r1; Halt];
+    let mut ids = Ids::default();
+    let mut um = UniversalMachine::builder(NullIO).program(&program).id_seed(7).build().unwrap();
+    um.run_with_hooks(&mut ids, None);
+    let id = um.registers.regs[1];
+    assert_ne!(id, 1);
+    assert_eq!(ids.0, [id, id]);
+}
@@ -0,0 +1,104 @@
+//! Array id scrambling behind the `randomize-ids` feature.
+
+use crate::um::Plate;
+
+const HALF: u32 = u32::BITS / 2;
+// Odd, so multiplying by it is invertible modulo 2^32.
+const MIX: u32 = 0x7F4A_7C15;
+const MIX_INVERSE: u32 = {
+    // Newton's iteration doubles the number of correct low bits each round.
+    let mut inv = MIX;
+    let mut i = 0;
+    while i < 5 {
+        inv = inv.wrapping_mul(2_u32.wrapping_sub(MIX.wrapping_mul(inv)));
+        i += 1;
+    }
+    inv
+};
+
+fn mix(mut x: u32) -> u32 {
+    x ^= x >> HALF;
+    x = x.wrapping_mul(MIX);
+    x ^ (x >> HALF)
+}
+
+fn unmix(mut x: u32) -> u32 {
+    x ^= x >> HALF;
+    x = x.wrapping_mul(MIX_INVERSE);
+    x ^ (x >> HALF)
+}
+
+// Scrambles the low 32 bits and keeps the rest, so that with `wide-address` ids stay
+// within the specification's 32 bits until the table outgrows them.
+#[allow(clippy::unnecessary_cast)]
+fn map_low(x: Plate, f: impl FnOnce(u32) -> u32) -> Plate {
+    let low = x as u32;
+    (x ^ low as Plate) | f(low) as Plate
+}
+
+/// Keyed permutation between array table slots and the ids handed to the program.
+/// Slot 0 always maps to id 0, so array 0 keeps its id.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IdPermutation {
+    key: u32,
+    offset: u32,
+}
+
+impl IdPermutation {
+    pub(crate) fn new(seed: u64) -> Self {
+        let key = seed as u32;
+        IdPermutation { key, offset: mix(key) }
+    }
+
+    /// Rebuilds the same permutation when passed to `new`.
+    pub(crate) fn seed(&self) -> u64 {
+        self.key.into()
+    }
+
+    /// Seeded from the standard library's per-process hash keys, or fixed without `std`.
+    pub(crate) fn random() -> Self {
+        #[cfg(feature = "std")]
+        let seed = {
+            use std::hash::BuildHasher;
+            std::collections::hash_map::RandomState::new().hash_one(0_u8)
+        };
+        #[cfg(not(feature = "std"))]
+        let seed = 0;
+        IdPermutation::new(seed)
+    }
+
+    #[inline(always)]
+    pub(crate) fn id(&self, slot: Plate) -> Plate {
+        map_low(slot, |low| mix(low ^ self.key) ^ self.offset)
+    }
+
+    #[inline(always)]
+    pub(crate) fn slot(&self, id: Plate) -> Plate {
+        map_low(id, |low| unmix(low ^ self.offset) ^ self.key)
+    }
+}
+
+#[test]
+fn round_trips_and_keeps_array_0() {
+    let ids = IdPermutation::new(0x1234_5678_9ABC);
+    assert_eq!(MIX.wrapping_mul(MIX_INVERSE), 1);
+    assert_eq!(ids.id(0), 0);
+    for slot in [1, 2, 3, 1000, Plate::MAX] {
+        assert_eq!(ids.slot(ids.id(slot)), slot);
+    }
+    assert_ne!(ids.id(1).wrapping_add(1), ids.id(2));
+    let rebuilt = IdPermutation::new(ids.seed());
+    assert_eq!(rebuilt.id(1000), ids.id(1000));
+}
+
+#[cfg(feature = "wide-address")]
+#[test]
+fn keeps_ids_within_32_bits() {
+    let ids = IdPermutation::new(0x1234_5678_9ABC);
+    for slot in [1, 2, 1000, u32::MAX as Plate] {
+        assert!(ids.id(slot) <= u32::MAX as Plate);
+    }
+    let wide = (1 << 32) | 5;
+    assert_eq!(ids.id(wide) >> 32, 1);
+    assert_eq!(ids.slot(ids.id(wide)), wide);
+}
@@ -0,0 +1,644 @@
+use std::{collections::VecDeque,
+          io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+          sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender}, Arc},
+          time::{Duration, Instant}};
+
+use crate::um::IOInterface;
+
+/// Console backed by arbitrary byte streams, optionally serving some preloaded input
+/// first. End of input is reported to the program as `0xFF`, as the specification
+/// requires. Output is buffered, and flushed whenever the program waits for input
+/// from `reader` so that prompts show up.
+pub struct BufferedIO<R: Read, W: Write> {
+    preloaded: VecDeque<u8>,
+    reader: BufReader<R>,
+    writer: BufWriter<W>,
+}
+
+#[deprecated(note = "renamed to `BufferedIO`")]
+pub type FileIO<R, W> = BufferedIO<R, W>;
+
+impl <R: Read, W: Write> BufferedIO<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        BufferedIO::with_preloaded(reader, writer, &[])
+    }
+
+    /// Serves `pre` as input before reading from `reader`.
+    pub fn with_preloaded(reader: R, writer: W, pre: &[u8]) -> Self {
+        BufferedIO {
+            preloaded: pre.iter().copied().collect(),
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    /// Serves `pre` as input before reading from `reader`, encoded as UTF-8: a
+    /// character outside ASCII takes several `request_input` calls, one per byte.
+    /// `inject_input_line` instead queues one Latin-1 byte per character.
+    pub fn from_utf8(reader: R, writer: W, pre: &str) -> Self {
+        BufferedIO::with_preloaded(reader, writer, pre.as_bytes())
+    }
+
+    /// Queues `bytes` after any preloaded input not read yet, ahead of `reader`.
+    pub fn inject_input(&mut self, bytes: &[u8]) {
+        self.preloaded.extend(bytes);
+    }
+
+    /// Queues `line` and a newline like `inject_input`, one byte per character in
+    /// Latin-1; characters beyond U+00FF become `?`.
+    pub fn inject_input_line(&mut self, line: &str) {
+        self.preloaded.extend(line.chars().map(|c| u8::try_from(c).unwrap_or(b'?')));
+        self.preloaded.push_back(b'\n');
+    }
+
+    /// The underlying writer. Output still sitting in the buffer is not visible here
+    /// until the machine halts or `flush` is called.
+    pub fn writer(&self) -> &W {
+        self.writer.get_ref()
+    }
+}
+
+impl <R: Read + Send, W: Write + Send> IOInterface for BufferedIO<R, W> {
+    fn request_input(&mut self) -> io::Result<u8> {
+        if let Some(byte) = self.preloaded.pop_front() {
+            return Ok(byte);
+        }
+        self.writer.flush()?;
+        let sigints = crate::um::sigint_count();
+        let mut byte = [0_u8];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(0xFF),
+                Ok(_) => return Ok(byte[0]),
+                // Ctrl-C gives up on the read, see `UniversalMachine::enable_signal_halt`.
+                Err(e) if e.kind() == ErrorKind::Interrupted && crate::um::sigint_count() == sigints => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.writer.write_all(&[ch])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Which side of a `TeeIO` answers `Input` requests.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputPolicy {
+    #[default]
+    FromA,
+    FromB,
+    /// Alternates between the two, starting with `a`.
+    RoundRobin,
+}
+
+/// Sends every output byte to both `a` and `b`, in that order.
+pub struct TeeIO<A: IOInterface, B: IOInterface> {
+    pub a: A,
+    pub b: B,
+    input_policy: InputPolicy,
+    read_from_b: bool,
+}
+
+impl <A: IOInterface, B: IOInterface> TeeIO<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        TeeIO::with_input_policy(a, b, InputPolicy::default())
+    }
+
+    pub fn with_input_policy(a: A, b: B, input_policy: InputPolicy) -> Self {
+        TeeIO { a, b, input_policy, read_from_b: false }
+    }
+}
+
+impl <A: IOInterface, B: IOInterface> IOInterface for TeeIO<A, B> {
+    fn request_input(&mut self) -> io::Result<u8> {
+        let from_b = match self.input_policy {
+            InputPolicy::FromA => false,
+            InputPolicy::FromB => true,
+            InputPolicy::RoundRobin => {
+                self.read_from_b = !self.read_from_b;
+                !self.read_from_b
+            },
+        };
+        if from_b {
+            self.b.request_input()
+        } else {
+            self.a.request_input()
+        }
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.a.request_output(ch)?;
+        self.b.request_output(ch)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.a.flush()?;
+        self.b.flush()
+    }
+
+    fn set_step_count(&mut self, steps: u64) {
+        self.a.set_step_count(steps);
+        self.b.set_step_count(steps);
+    }
+}
+
+/// Forwards at most `limit` output bytes to the wrapped interface and raises a shared
+/// stop flag once the limit is reached; later output is dropped. The flag does not
+/// stop the machine by itself: drive it with `UniversalMachine::run_until_stopped`,
+/// or check `stop_flag` between `step` calls.
+pub struct LimitedIO<I: IOInterface> {
+    pub inner: I,
+    remaining: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl <I: IOInterface> LimitedIO<I> {
+    pub fn new(inner: I, limit: usize) -> Self {
+        LimitedIO {
+            inner,
+            remaining: limit,
+            stop: Arc::new(AtomicBool::new(limit == 0)),
+        }
+    }
+
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+}
+
+impl <I: IOInterface> IOInterface for LimitedIO<I> {
+    fn request_input(&mut self) -> io::Result<u8> {
+        self.inner.request_input()
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        if self.remaining == 0 {
+            return Ok(());
+        }
+        self.inner.request_output(ch)?;
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn set_step_count(&mut self, steps: u64) {
+        self.inner.set_step_count(steps)
+    }
+}
+
+/// Reports end of input (`0xFF`) for every read once `timeout` has passed since
+/// construction. The deadline is checked before each read; a read already blocked in
+/// the wrapped interface is not interrupted.
+pub struct TimeoutIO<I: IOInterface> {
+    pub inner: I,
+    deadline: Instant,
+    timed_out: bool,
+}
+
+impl <I: IOInterface> TimeoutIO<I> {
+    pub fn new(inner: I, timeout: Duration) -> Self {
+        TimeoutIO { inner, deadline: Instant::now() + timeout, timed_out: false }
+    }
+
+    /// Whether a read has been cut off by the timeout.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+}
+
+impl <I: IOInterface> IOInterface for TimeoutIO<I> {
+    fn request_input(&mut self) -> io::Result<u8> {
+        if !self.timed_out && Instant::now() >= self.deadline {
+            self.timed_out = true;
+        }
+        if self.timed_out {
+            return Ok(0xFF);
+        }
+        self.inner.request_input()
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.inner.request_output(ch)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn set_step_count(&mut self, steps: u64) {
+        self.inner.set_step_count(steps)
+    }
+}
+
+/// Whether an `IOEvent` was read by the program or written by it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IODirection {
+    Input,
+    Output,
+}
+
+/// One byte of console traffic and the number of instructions executed before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IOEvent {
+    pub direction: IODirection,
+    pub byte: u8,
+    pub step: u64,
+}
+
+enum ReplayMode<'a> {
+    Record(Box<dyn IOInterface + 'a>),
+    Replay(VecDeque<IOEvent>),
+}
+
+/// Records a console session, or plays a recorded one back without the original console.
+///
+/// While recording, every request is forwarded to the wrapped interface and logged.
+/// While replaying, input is served from the recording and output is checked against
+/// it; a program that asks for input where output was recorded, writes a different
+/// byte, or runs past the end of the recording fails with `ErrorKind::InvalidData`.
+/// Step counts are kept for reference but not compared.
+pub struct ReplayIO<'a> {
+    mode: ReplayMode<'a>,
+    events: Vec<IOEvent>,
+    step: u64,
+}
+
+impl <'a> ReplayIO<'a> {
+    pub fn record(inner: impl IOInterface + 'a) -> Self {
+        ReplayIO::with_mode(ReplayMode::Record(Box::new(inner)))
+    }
+
+    pub fn replay(events: impl IntoIterator<Item = IOEvent>) -> Self {
+        ReplayIO::with_mode(ReplayMode::Replay(events.into_iter().collect()))
+    }
+
+    fn with_mode(mode: ReplayMode<'a>) -> Self {
+        ReplayIO { mode, events: Vec::new(), step: 0 }
+    }
+
+    /// Events that have gone through this interface so far, in either mode.
+    pub fn events(&self) -> &[IOEvent] {
+        &self.events
+    }
+
+    /// Writes `events` as text, one `I|O <byte> <step>` line per event.
+    pub fn save_replay<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for event in &self.events {
+            let direction = match event.direction {
+                IODirection::Input => 'I',
+                IODirection::Output => 'O',
+            };
+            writeln!(w, "{} {} {}", direction, event.byte, event.step)?;
+        }
+        w.flush()
+    }
+
+    /// Reads a recording written by `save_replay` and returns an interface replaying it.
+    pub fn load_replay<R: Read>(r: R) -> io::Result<Self> {
+        let mut events = Vec::new();
+        for line in BufReader::new(r).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            events.push(parse_event(&line).ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidData, format!("malformed replay event `{}`", line))
+            })?);
+        }
+        Ok(ReplayIO::replay(events))
+    }
+
+    fn next_recorded(&mut self, direction: IODirection) -> io::Result<u8> {
+        let ReplayMode::Replay(queue) = &mut self.mode else {
+            unreachable!("only called while replaying");
+        };
+        match queue.pop_front() {
+            Some(event) if event.direction == direction => Ok(event.byte),
+            Some(event) => Err(io::Error::new(ErrorKind::InvalidData,
+                                              format!("expected {:?} at step {}", event.direction, self.step))),
+            None => Err(io::Error::new(ErrorKind::InvalidData,
+                                       format!("recording ended before {:?} at step {}", direction, self.step))),
+        }
+    }
+
+    fn log(&mut self, direction: IODirection, byte: u8) {
+        self.events.push(IOEvent { direction, byte, step: self.step });
+    }
+}
+
+fn parse_event(line: &str) -> Option<IOEvent> {
+    let mut fields = line.split_whitespace();
+    let direction = match fields.next()? {
+        "I" => IODirection::Input,
+        "O" => IODirection::Output,
+        _ => return None,
+    };
+    let byte = fields.next()?.parse().ok()?;
+    let step = fields.next()?.parse().ok()?;
+    match fields.next() {
+        Some(_) => None,
+        None => Some(IOEvent { direction, byte, step }),
+    }
+}
+
+impl IOInterface for ReplayIO<'_> {
+    fn request_input(&mut self) -> io::Result<u8> {
+        let byte = match &mut self.mode {
+            ReplayMode::Record(inner) => inner.request_input()?,
+            ReplayMode::Replay(_) => self.next_recorded(IODirection::Input)?,
+        };
+        self.log(IODirection::Input, byte);
+        Ok(byte)
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        match &mut self.mode {
+            ReplayMode::Record(inner) => inner.request_output(ch)?,
+            ReplayMode::Replay(_) => {
+                let expected = self.next_recorded(IODirection::Output)?;
+                if expected != ch {
+                    return Err(io::Error::new(ErrorKind::InvalidData,
+                                              format!("wrote {:#04x} at step {}, recorded {:#04x}",
+                                                      ch, self.step, expected)));
+                }
+            },
+        }
+        self.log(IODirection::Output, ch);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.mode {
+            ReplayMode::Record(inner) => inner.flush(),
+            ReplayMode::Replay(_) => Ok(()),
+        }
+    }
+
+    fn set_step_count(&mut self, steps: u64) {
+        self.step = steps;
+        if let ReplayMode::Record(inner) = &mut self.mode {
+            inner.set_step_count(steps);
+        }
+    }
+}
+
+/// One end of a pair of byte channels, for driving a machine from another thread.
+/// `Input` blocks until the host sends a byte and reads `0xFF` once the host's end is
+/// dropped; `Output` fails with `ErrorKind::BrokenPipe` after that.
+pub struct ChannelIO {
+    pub tx: Sender<u8>,
+    pub rx: Receiver<u8>,
+}
+
+impl ChannelIO {
+    /// Returns the machine's end and the host's end; bytes sent on one are received
+    /// on the other.
+    pub fn pair() -> (ChannelIO, ChannelIO) {
+        let (machine_tx, host_rx) = mpsc::channel();
+        let (host_tx, machine_rx) = mpsc::channel();
+        (ChannelIO { tx: machine_tx, rx: machine_rx }, ChannelIO { tx: host_tx, rx: host_rx })
+    }
+}
+
+impl IOInterface for ChannelIO {
+    fn request_input(&mut self) -> io::Result<u8> {
+        Ok(self.rx.recv().unwrap_or(0xFF))
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.tx.send(ch).map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "host end of the channel is closed"))
+    }
+}
+
+/// Console behind `UniversalMachine::run_interactive`. Output is held back until a
+/// newline, after which a whole line of input is read before the program continues.
+/// Input the program has not consumed yet stays queued for later reads.
+pub(crate) struct InteractiveIO<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+    line: Vec<u8>,
+    input: VecDeque<u8>,
+}
+
+impl <R: BufRead, W: Write> InteractiveIO<R, W> {
+    pub(crate) fn new(reader: R, writer: W) -> Self {
+        InteractiveIO { reader, writer, line: Vec::new(), input: VecDeque::new() }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.line)?;
+        self.line.clear();
+        self.writer.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<()> {
+        let mut line = Vec::new();
+        self.reader.read_until(b'\n', &mut line)?;
+        self.input.extend(line);
+        Ok(())
+    }
+}
+
+impl <R: BufRead + Send, W: Write + Send> IOInterface for InteractiveIO<R, W> {
+    fn request_input(&mut self) -> io::Result<u8> {
+        if self.input.is_empty() {
+            // Show a prompt that does not end in a newline before blocking.
+            self.write_line()?;
+            self.read_line()?;
+        }
+        Ok(self.input.pop_front().unwrap_or(0xFF))
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.line.push(ch);
+        if ch == b'\n' {
+            self.write_line()?;
+            self.read_line()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_line()
+    }
+}
+
+#[test]
+fn flushes_on_halt() {
+    use crate::um::{program_bytes, UniversalMachine};
+
+    // in r0; out r0; in r0; out r0; halt
+    let program = program_bytes(&[0xB0000000, 0xA0000000, 0xB0000000, 0xA0000000, 0x70000000]);
+    let mut io = BufferedIO::new(&b"x"[..], Vec::new());
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    um.run();
+    drop(um);
+    assert_eq!(io.writer(), &[b'x', 0xFF]);
+}
+
+#[test]
+fn serves_preloaded_input_first() {
+    let mut io = BufferedIO::with_preloaded(&b"b"[..], Vec::new(), b"a");
+    io.request_output(b'>').unwrap();
+    assert_eq!(io.request_input().unwrap(), b'a');
+    assert!(io.writer().is_empty());
+    // Waiting on the reader shows the prompt first.
+    assert_eq!(io.request_input().unwrap(), b'b');
+    assert_eq!(io.writer(), b">");
+    assert_eq!(io.request_input().unwrap(), 0xFF);
+}
+
+#[test]
+fn from_utf8_keeps_multibyte_characters() {
+    let mut io = BufferedIO::from_utf8(&[][..], Vec::new(), "aé");
+    io.inject_input_line("é");
+    let read: Vec<u8> = (0..6).map(|_| io.request_input().unwrap()).collect();
+    assert_eq!(read, [b'a', 0xC3, 0xA9, 0xE9, b'\n', 0xFF]);
+}
+
+#[test]
+fn injects_input_ahead_of_reader() {
+    let mut io = BufferedIO::with_preloaded(&b"z"[..], Vec::new(), b"a");
+    io.inject_input(b"b");
+    assert_eq!(io.request_input().unwrap(), b'a');
+    io.inject_input_line("é\u{263A}");
+    let read: Vec<u8> = (0..5).map(|_| io.request_input().unwrap()).collect();
+    assert_eq!(read, [b'b', 0xE9, b'?', b'\n', b'z']);
+}
+
+#[test]
+fn tee_duplicates_output() {
+    use crate::{testing::RecordingIO, um::{program_bytes, UniversalMachine}};
+
+    // in r0; out r0; in r0; out r0; halt
+    let program = program_bytes(&[0xB0000000, 0xA0000000, 0xB0000000, 0xA0000000, 0x70000000]);
+    let mut tee = TeeIO::with_input_policy(RecordingIO::new(b"ab"), RecordingIO::new(b"xy"),
+                                           InputPolicy::RoundRobin);
+    UniversalMachine::new(&program, &mut tee).unwrap().run();
+    assert_eq!(tee.a.output_bytes(), b"ax");
+    assert_eq!(tee.b.output_bytes(), b"ax");
+}
+
+#[test]
+fn limited_io_stops_after_limit() {
+    use crate::{testing::RecordingIO, um::{program_bytes, UniversalMachine}};
+
+    // r0 <- 'z'; loop: out r0; r2 <- 1; jump to r2
+    let program = program_bytes(&[0xD000007A, 0xA0000000, 0xD4000001, 0xC000000A]);
+    let mut io = LimitedIO::new(RecordingIO::new(&[]), 3);
+    let stop = io.stop_flag();
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    um.run_until_stopped(&stop);
+    assert!(!um.is_halted);
+    drop(um);
+    assert_eq!(io.inner.output_bytes(), b"zzz");
+}
+
+#[test]
+fn timeout_io_reports_end_of_input() {
+    use crate::testing::RecordingIO;
+
+    let mut io = TimeoutIO::new(RecordingIO::new(b"ab"), Duration::from_secs(60));
+    assert_eq!(io.request_input().unwrap(), b'a');
+    assert!(!io.timed_out());
+
+    let mut io = TimeoutIO::new(RecordingIO::new(b"ab"), Duration::ZERO);
+    assert_eq!(io.request_input().unwrap(), 0xFF);
+    assert_eq!(io.request_input().unwrap(), 0xFF);
+    assert!(io.timed_out());
+}
+
+#[test]
+fn run_reports_io_errors() {
+    use crate::um::{program_bytes, RunOutcome, UmError, UniversalMachine};
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(ErrorKind::BrokenPipe, "closed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // in r0; out r0; halt, which flushes the buffered output
+    let program = program_bytes(&[0xB0000000, 0xA0000000, 0x70000000]);
+    let reader = io::Cursor::new(b"x");
+    let mut io = BufferedIO::new(reader, FailingWriter);
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    match um.run() {
+        RunOutcome::IOError(e) => assert_eq!(e.kind(), ErrorKind::BrokenPipe),
+        outcome => panic!("unexpected outcome {:?}", outcome),
+    }
+    assert_eq!(um.halt_reason(), Some(UmError::IoFailed { ip: 2, kind: ErrorKind::BrokenPipe }));
+}
+
+#[test]
+fn replays_recorded_session() {
+    use crate::{testing::RecordingIO, um::{program_bytes, RunOutcome, UniversalMachine}};
+
+    // in r0; out r0; in r0; out r0; halt
+    let program = program_bytes(&[0xB0000000, 0xA0000000, 0xB0000000, 0xA0000000, 0x70000000]);
+    let mut recorder = ReplayIO::record(RecordingIO::new(b"hi"));
+    UniversalMachine::new(&program, &mut recorder).unwrap().run();
+    assert_eq!(recorder.events()[3], IOEvent { direction: IODirection::Output, byte: b'i', step: 3 });
+    let mut saved = Vec::new();
+    recorder.save_replay(&mut saved).unwrap();
+    assert_eq!(saved, b"I 104 0\nO 104 1\nI 105 2\nO 105 3\n");
+
+    let mut replay = ReplayIO::load_replay(&saved[..]).unwrap();
+    assert!(matches!(UniversalMachine::new(&program, &mut replay).unwrap().run(), RunOutcome::Halted));
+    assert_eq!(replay.events(), recorder.events());
+
+    // out r0 where input was recorded
+    let diverging = program_bytes(&[0xA0000000, 0x70000000]);
+    let mut replay = ReplayIO::load_replay(&saved[..]).unwrap();
+    match UniversalMachine::new(&diverging, &mut replay).unwrap().run() {
+        RunOutcome::IOError(e) => assert_eq!(e.kind(), ErrorKind::InvalidData),
+        outcome => panic!("unexpected outcome {:?}", outcome),
+    }
+    assert!(ReplayIO::load_replay(&b"X 1 2\n"[..]).is_err());
+}
+
+#[test]
+fn channel_io_echoes_from_thread() {
+    use crate::um::{program_bytes, RunOutcome, UniversalMachine};
+
+    // in r0; out r0; in r0; out r0; halt
+    let program = program_bytes(&[0xB0000000, 0xA0000000, 0xB0000000, 0xA0000000, 0x70000000]);
+    let (machine_io, host) = ChannelIO::pair();
+    let machine = std::thread::spawn(move || UniversalMachine::new(&program, machine_io).unwrap().run());
+    host.tx.send(b'q').unwrap();
+    assert_eq!(host.rx.recv(), Ok(b'q'));
+    drop(host.tx);
+    assert_eq!(host.rx.recv(), Ok(0xFF));
+    assert!(matches!(machine.join().unwrap(), RunOutcome::Halted));
+}
+
+#[test]
+fn interactive_io_reads_a_line_after_each_output_line() {
+    use crate::um::{program_bytes, UniversalMachine};
+
+    // r1 <- '\n'; out r1; in r0; out r0; out r1; in r0; out r0; halt
+    let program = program_bytes(&[0xD200000A, 0xA0000001, 0xB0000000, 0xA0000000, 0xA0000001,
+                                  0xB0000000, 0xA0000000, 0x70000000]);
+    let mut io = InteractiveIO::new(&b"a\n"[..], Vec::new());
+    UniversalMachine::new(&program, &mut io).unwrap().run();
+    // The second line is read at end of input and yields nothing, so the last `in` reads the
+    // newline typed after `a`.
+    assert_eq!(io.writer, b"\na\n\n");
+}
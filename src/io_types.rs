@@ -0,0 +1,62 @@
+//! Error types used by `IOInterface`. With the `std` feature these are `std::io`'s
+//! own; without it, minimal stand-ins that only carry an `ErrorKind`.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use core::fmt;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        Interrupted,
+        UnexpectedEof,
+        BrokenPipe,
+        Other,
+    }
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let text = match self {
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::BrokenPipe => "broken pipe",
+                ErrorKind::Other => "other error",
+            };
+            f.write_str(text)
+        }
+    }
+
+    /// Build one with `ErrorKind::Other.into()`, which also works with `std::io::Error`.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error { kind }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.kind.fmt(f)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    pub type Result<T> = core::result::Result<T, Error>;
+}
+
+#[cfg(not(feature = "std"))]
+pub use no_std::{Error, ErrorKind, Result};
@@ -0,0 +1,329 @@
+use cranelift_codegen::{ir::{AbiParam, InstBuilder, MemFlagsData, Type, Value}, settings::{self, Configurable}};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::Module;
+use rustc_hash::FxHashMap;
+
+use crate::um::{Command, Plate, UmError, UniversalMachine};
+
+/// Interpreted visits of a block start before it gets compiled.
+pub const DEFAULT_JIT_THRESHOLD: u32 = 1000;
+
+/// Shorter blocks are left to the interpreter; calling into them costs more than it saves.
+const MIN_BLOCK_LEN: usize = 4;
+
+type BlockFn = unsafe extern "C" fn(*mut Plate);
+
+struct CompiledBlock {
+    code: BlockFn,
+    len: usize,
+}
+
+/// Runs a machine, compiling hot straight-line runs of register-only instructions
+/// (`CMOV`, `ADD`, `MUL`, `NAND`, `STORE_CONST`) into native code with Cranelift.
+///
+/// Every other instruction is interpreted. Blocks start at jump targets and after
+/// compiled blocks; one is compiled once execution has arrived at its start
+/// `threshold` times. Storing into array 0, by the program or through `write_array`,
+/// drops the blocks that cover the written offset, and replacing array 0 with
+/// `LoadProg` drops all of them; their code stays allocated until the `JitMachine` is
+/// dropped.
+///
+/// Only loops doing arithmetic in registers benefit; programs dominated by array
+/// accesses and jumps run somewhat slower than on the plain interpreter.
+///
+/// Instructions executed inside compiled blocks are counted in `steps_executed` but
+/// are not seen by the tracer, hooks or instruction counters, and `step_limit` is not
+/// applied.
+pub struct JitMachine<'a> {
+    // Private so that array 0 cannot change behind the compiled blocks.
+    machine: UniversalMachine<'a>,
+    threshold: u32,
+    // Indexed by offset in array 0, grown on demand.
+    visits: Vec<u32>,
+    covered: Vec<bool>,
+    blocks: FxHashMap<usize, CompiledBlock>,
+    // Only `None` while being dropped.
+    module: Option<JITModule>,
+}
+
+impl <'a> JitMachine<'a> {
+    pub fn new(machine: UniversalMachine<'a>) -> Self {
+        JitMachine::with_threshold(machine, DEFAULT_JIT_THRESHOLD)
+    }
+
+    pub fn with_threshold(machine: UniversalMachine<'a>, threshold: u32) -> Self {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").unwrap();
+        let isa = cranelift_native::builder()
+            .expect("host architecture is not supported by Cranelift")
+            .finish(settings::Flags::new(flags))
+            .unwrap();
+        let module = JITModule::new(JITBuilder::with_isa(isa, cranelift_module::default_libcall_names()));
+        JitMachine {
+            machine,
+            threshold,
+            visits: Vec::new(),
+            covered: Vec::new(),
+            blocks: FxHashMap::default(),
+            module: Some(module),
+        }
+    }
+
+    pub fn machine(&self) -> &UniversalMachine<'a> {
+        &self.machine
+    }
+
+    /// Mutable access to the machine. Array 0 can be changed through it without the
+    /// `JitMachine` noticing, so every compiled block is dropped first.
+    pub fn machine_mut(&mut self) -> &mut UniversalMachine<'a> {
+        self.drop_blocks();
+        &mut self.machine
+    }
+
+    /// Same as `UniversalMachine::write_array`, but a store into array 0 drops the
+    /// compiled blocks covering `offset`.
+    pub fn write_array(&mut self, id: Plate, offset: usize, value: Plate) -> Result<(), UmError> {
+        if id == 0 && self.covered.get(offset) == Some(&true) {
+            self.invalidate(offset);
+        }
+        self.machine.write_array(id, offset, value)
+    }
+
+    /// Same as `UniversalMachine::patch_instruction`, dropping the compiled blocks
+    /// covering `offset`.
+    pub fn patch_instruction(&mut self, offset: usize, cmd: Command) -> Result<(), UmError> {
+        self.write_array(0, offset, cmd.encode())
+    }
+
+    pub fn compiled_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Runs until the machine halts.
+    pub fn run(&mut self) {
+        while self.step_block() {}
+    }
+
+    /// Executes one compiled block, or interprets up to the next jump. Returns `true`
+    /// while the machine is still running.
+    pub fn step_block(&mut self) -> bool {
+        if self.machine.is_halted {
+            return false;
+        }
+        let ip = self.machine.ip;
+        if let Some(block) = self.blocks.get(&ip) {
+            // The block only reads and writes the eight registers it is handed.
+            unsafe { (block.code)(self.machine.registers.regs.as_mut_ptr()) };
+            self.machine.ip += block.len;
+            self.machine.record_jitted_steps(block.len as u64);
+            return true;
+        }
+        if ip >= self.visits.len() {
+            self.visits.resize(ip + 1, 0);
+        }
+        self.visits[ip] = self.visits[ip].saturating_add(1);
+        // Compilation is tried once, when the offset becomes hot.
+        if self.visits[ip] == self.threshold && self.compile(ip) {
+            return true;
+        }
+        let registers = &self.machine.registers;
+        match self.fetch(ip) {
+            Some(Command::ArrStore { arr, offset, .. }) if registers[arr] == 0 => {
+                let offset = registers[offset] as usize;
+                if self.covered.get(offset) == Some(&true) {
+                    self.invalidate(offset);
+                }
+                self.machine.step()
+            },
+            Some(Command::LoadProg { arr, .. }) if registers[arr] != 0 => {
+                self.drop_blocks();
+                self.machine.step()
+            },
+            _ => self.machine.run_until_jump(&self.covered),
+        }
+    }
+
+    /// Drops every block; all starts have to get hot again before being recompiled.
+    fn drop_blocks(&mut self) {
+        self.blocks.clear();
+        self.visits.clear();
+        self.covered.clear();
+    }
+
+    /// Drops every block that contains `offset`; their starts have to get hot again
+    /// before being recompiled.
+    fn invalidate(&mut self, offset: usize) {
+        let visits = &mut self.visits;
+        self.blocks.retain(|&start, block| {
+            let keep = !(start..start + block.len).contains(&offset);
+            if !keep {
+                visits[start] = 0;
+            }
+            keep
+        });
+        self.covered.fill(false);
+        for (&start, block) in &self.blocks {
+            self.covered[start..start + block.len].fill(true);
+        }
+    }
+
+    fn fetch(&self, offset: usize) -> Option<Command> {
+        let program = self.machine.memory_map().array_slice(0)?;
+        program.get(offset).map(|&p| Command::decode(p))
+    }
+
+    /// Compiles the block starting at `ip`. Returns `false` if it is shorter than
+    /// `MIN_BLOCK_LEN` or Cranelift rejects it.
+    fn compile(&mut self, ip: usize) -> bool {
+        let commands: Vec<Command> = (ip..)
+            .map_while(|offset| self.fetch(offset).filter(is_register_only))
+            .collect();
+        if commands.len() < MIN_BLOCK_LEN {
+            return false;
+        }
+        match self.build_function(&commands) {
+            Some(code) => {
+                let end = ip + commands.len();
+                if end > self.covered.len() {
+                    self.covered.resize(end, false);
+                }
+                self.covered[ip..end].fill(true);
+                self.blocks.insert(ip, CompiledBlock { code, len: commands.len() });
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn build_function(&mut self, commands: &[Command]) -> Option<BlockFn> {
+        let module = self.module.as_mut()?;
+        let ty = Type::int(Plate::BITS as u16)?;
+        let target_config = module.target_config();
+        let mut ctx = module.make_context();
+        ctx.func.signature.params.push(AbiParam::new(target_config.pointer_type()));
+
+        let mut builder_ctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        b.seal_block(entry);
+        let base = b.block_params(entry)[0];
+        let flags = MemFlagsData::trusted();
+        let width = ty.bytes() as i32;
+        let mut regs: [Value; 8] = std::array::from_fn(|i| b.ins().load(ty, flags, base, i as i32 * width));
+        for command in commands {
+            match *command {
+                Command::CondMove { dst, src, cnd } => {
+                    let (dst, src, cnd) = (dst as usize, src as usize, cnd as usize);
+                    regs[dst] = b.ins().select(regs[cnd], regs[src], regs[dst]);
+                },
+                Command::Add { dst, op1, op2 } => {
+                    regs[dst as usize] = b.ins().iadd(regs[op1 as usize], regs[op2 as usize]);
+                },
+                Command::Mul { dst, op1, op2 } => {
+                    regs[dst as usize] = b.ins().imul(regs[op1 as usize], regs[op2 as usize]);
+                },
+                Command::NotAnd { dst, op1, op2 } => {
+                    let and = b.ins().band(regs[op1 as usize], regs[op2 as usize]);
+                    regs[dst as usize] = b.ins().bnot(and);
+                },
+                Command::StoreConst { dst, val } => {
+                    regs[dst as usize] = b.ins().iconst(ty, val as i64);
+                },
+                _ => unreachable!("{} is not a register-only instruction", command),
+            }
+        }
+        for (i, &reg) in regs.iter().enumerate() {
+            b.ins().store(flags, reg, base, i as i32 * width);
+        }
+        b.ins().return_(&[]);
+        b.finalize(target_config);
+
+        let id = module.declare_anonymous_function(&ctx.func.signature).ok()?;
+        module.define_function(id, &mut ctx).ok()?;
+        module.clear_context(&mut ctx);
+        module.finalize_definitions().ok()?;
+        let code = module.get_finalized_function(id);
+        // The function was built with the host's default calling convention and the
+        // signature above.
+        Some(unsafe { std::mem::transmute::<*const u8, BlockFn>(code) })
+    }
+}
+
+impl Drop for JitMachine<'_> {
+    fn drop(&mut self) {
+        self.blocks.clear();
+        if let Some(module) = self.module.take() {
+            // No compiled function can be called any more.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+fn is_register_only(command: &Command) -> bool {
+    matches!(command, Command::CondMove { .. } | Command::Add { .. } | Command::Mul { .. }
+             | Command::NotAnd { .. } | Command::StoreConst { .. })
+}
+
+#[test]
+fn matches_interpreter() {
+    use crate::{assembler::Assembler, testing::NullIO};
+
+    let program = Assembler::assemble("
+        STORE_CONST r1, 1000
+        NAND r3, r2, r2
+        STORE_CONST r4, loop
+        STORE_CONST r5, done
+    loop:
+        MUL r7, r1, r1
+        ADD r0, r0, r7
+        ADD r1, r1, r3
+        ADD r6, r5, r2
+        CMOV r6, r4, r1
+        LOAD_PROG r2, r6
+    done:
+        HALT
+    ").unwrap();
+    let mut interpreted = UniversalMachine::new(&program, NullIO).unwrap();
+    interpreted.run();
+    let mut jit = JitMachine::with_threshold(UniversalMachine::new(&program, NullIO).unwrap(), 2);
+    jit.run();
+    assert!(jit.compiled_blocks() > 0);
+    assert_eq!(jit.machine().registers.regs, interpreted.registers.regs);
+    assert_eq!(jit.machine().steps_executed(), interpreted.steps_executed());
+}
+
+#[test]
+fn host_writes_to_array_0_drop_compiled_blocks() {
+    use crate::{assembler::Assembler, testing::NullIO};
+
+    // Counts r0 up by 2 five times.
+    let program = Assembler::assemble("
+        STORE_CONST r1, 5
+        NAND r3, r2, r2
+        STORE_CONST r4, loop
+        STORE_CONST r5, done
+    loop:
+        STORE_CONST r7, 2
+        ADD r0, r0, r7
+        ADD r1, r1, r3
+        ADD r6, r5, r2
+        CMOV r6, r4, r1
+        LOAD_PROG r2, r6
+    done:
+        HALT
+    ").unwrap();
+    let mut jit = JitMachine::with_threshold(UniversalMachine::new(&program, NullIO).unwrap(), 2);
+    while jit.compiled_blocks() == 0 {
+        jit.step_block();
+    }
+    let done_so_far = jit.machine().registers.regs[0];
+    jit.patch_instruction(4, Command::StoreConst { dst: 7, val: 100 }).unwrap();
+    assert_eq!(jit.compiled_blocks(), 0);
+    jit.run();
+    let passes_left = jit.machine().registers.regs[0] - done_so_far;
+    assert_eq!(passes_left % 100, 0);
+    assert!(passes_left > 0);
+}
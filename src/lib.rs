@@ -0,0 +1,33 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod um;
+pub mod disasm;
+pub mod debugger;
+pub mod trace;
+pub mod counters;
+pub mod memory;
+pub mod static_machine;
+pub mod multi;
+#[cfg(feature = "randomize-ids")]
+mod ids;
+pub mod diff;
+pub mod strings;
+#[cfg(feature = "std")]
+pub mod io;
+pub mod io_types;
+pub mod testing;
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod assembler;
+#[cfg(feature = "std")]
+pub mod cycle;
+pub mod hooks;
+pub mod callstack;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "metrics")]
+pub mod metrics;
@@ -1,15 +1,11 @@
 #![allow(dead_code)]
-#![feature(new_uninit)]
 
 use std::{
-    collections::VecDeque,
     fs::File,
-    io::{stdin, stdout, Read, Write},
+    io::{stdin, stdout, Read},
 };
 
-use um::{IOInterface, UniversalMachine};
-
-mod um;
+use cult_of_the_bound_var::{io::BufferedIO, um::UniversalMachine};
 
 fn codex() -> Vec<u8> {
     let mut f = File::open("data/codex.umz").unwrap();
@@ -25,44 +21,19 @@ fn sandmark() -> Vec<u8> {
     buf
 }
 
-struct UMIO {
-    input_buffer: VecDeque<u8>,
-}
-
-impl UMIO {
-    fn new(s: &str) -> Self {
-        return UMIO {
-            input_buffer: s.chars().map(|c| c as u8).collect(),
-        };
-    }
-}
-
-impl IOInterface for UMIO {
-    fn request_input(&mut self) -> u8 {
-        match self.input_buffer.pop_front() {
-            Some(c) => c,
-            None => {
-                let mut stdin_handle = stdin().lock();
-                let mut byte = [0_u8];
-                stdin_handle.read_exact(&mut byte).unwrap();
-                byte[0]
-            }
-        }
-    }
-
-    fn request_output(&mut self, ch: u8) {
-        stdout().lock().write(&[ch]).unwrap();
-    }
-}
-
 fn run_codex() {
-    let mut io = UMIO::new(r"(\b.bb)(\v.vv)06FHPVboundvarHRAk");
+    let mut io = BufferedIO::with_preloaded(stdin(), stdout(), br"(\b.bb)(\v.vv)06FHPVboundvarHRAk");
     let mut um = UniversalMachine::new(&codex(), &mut io).unwrap();
+    #[cfg(unix)]
+    um.enable_signal_halt();
     um.run();
+    if let Some(reason) = um.halt_reason() {
+        eprintln!("{}: {}", reason, um);
+    }
 }
 
 fn run_sandmark() {
-    let mut io = UMIO::new(r"");
+    let mut io = BufferedIO::new(stdin(), stdout());
     let mut um = UniversalMachine::new(&sandmark(), &mut io).unwrap();
     um.run();
 }
@@ -73,9 +44,9 @@ fn main() {
 
 #[test]
 fn bench() {
-    let t = Instant::now();
-    let mut io = UMIO::new(r"");
+    let t = std::time::Instant::now();
+    let mut io = BufferedIO::new(stdin(), stdout());
     let mut um = UniversalMachine::new(&sandmark(), &mut io).unwrap();
     um.run();
     eprintln!("Elapsed: {:?}", t.elapsed());
-}
\ No newline at end of file
+}
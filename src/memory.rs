@@ -0,0 +1,64 @@
+use crate::um::{Arrays, IdMap, Plate};
+
+/// Read-only view of the arrays currently allocated by a machine.
+pub struct MemoryMap<'m> {
+    arrays: &'m Arrays,
+    ids: IdMap,
+}
+
+impl <'m> MemoryMap<'m> {
+    pub(crate) fn new(arrays: &'m Arrays, ids: IdMap) -> Self {
+        MemoryMap { arrays, ids }
+    }
+
+    /// Identifiers of all live arrays in table order, starting with the program array 0;
+    /// ascending unless the `randomize-ids` feature scrambles them.
+    pub fn allocated_ids(&self) -> impl Iterator<Item = Plate> + 'm {
+        let ids = self.ids;
+        self.arrays.iter()
+            .enumerate()
+            .filter(|(_, a)| a.is_some())
+            .map(move |(slot, _)| ids.id(slot as Plate))
+    }
+
+    pub fn array_len(&self, id: Plate) -> Option<usize> {
+        self.array_slice(id).map(|a| a.len())
+    }
+
+    pub fn array_slice(&self, id: Plate) -> Option<&'m [Plate]> {
+        self.arrays.get(self.ids.slot(id))?.as_deref()
+    }
+}
+
+/// Memory held by a machine's live arrays, counted in platters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub current_words: u64,
+    pub peak_words: u64,
+}
+
+/// `Alloc` and `Free` activity of a machine; `live_arrays` excludes the program array 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationStats {
+    pub alloc_count: u64,
+    pub free_count: u64,
+    pub live_arrays: usize,
+}
+
+#[test]
+fn reflects_alloc_and_free() {
+    use crate::{testing::NullIO, um::{program_bytes, UniversalMachine}};
+
+    // r0 <- 3; r1 <- new[r0]; r2 <- new[r0]; free r1; halt
+    let program = program_bytes(&[0xD0000003, 0x80000008, 0x80000010, 0x90000001, 0x70000000]);
+    let mut io = NullIO;
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    um.run();
+    let [_, freed, live, ..] = um.registers.regs;
+    let map = um.memory_map();
+    assert_eq!(map.allocated_ids().collect::<Vec<_>>(), vec![0, live]);
+    assert_eq!(map.array_len(0), Some(5));
+    assert_eq!(map.array_slice(live), Some(&[0, 0, 0][..]));
+    assert_eq!(map.array_len(freed), None);
+    assert_eq!(map.array_len(7), None);
+}
@@ -0,0 +1,113 @@
+use ::metrics::{counter, gauge, Counter, Gauge};
+
+use crate::{counters::OPCODE_NAMES, um::UniversalMachine};
+
+/// Handles to a machine's metrics in the `metrics` recorder installed when they
+/// were created, published by `UniversalMachine::record_metrics`:
+///
+/// - `um_instructions_total{opcode="add"}`, one counter per opcode
+/// - `um_memory_words_current` and `um_memory_words_peak`
+/// - `um_arrays_allocated_total` and `um_arrays_freed_total`
+/// - `um_arrays_live`, not counting array 0
+pub struct Metrics {
+    pub(crate) instructions: [Counter; 14],
+    pub(crate) memory_words_current: Gauge,
+    pub(crate) memory_words_peak: Gauge,
+    pub(crate) arrays_allocated: Counter,
+    pub(crate) arrays_freed: Counter,
+    pub(crate) arrays_live: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            instructions: OPCODE_NAMES.map(|name| counter!("um_instructions_total", "opcode" => name.to_lowercase())),
+            memory_words_current: gauge!("um_memory_words_current"),
+            memory_words_peak: gauge!("um_memory_words_peak"),
+            arrays_allocated: counter!("um_arrays_allocated_total"),
+            arrays_freed: counter!("um_arrays_freed_total"),
+            arrays_live: gauge!("um_arrays_live"),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl UniversalMachine<'_> {
+    /// Publishes the machine's current counts. Counters are set to absolute values,
+    /// so this can be called as often as the scraper needs fresh data.
+    pub fn record_metrics(&self, metrics: &Metrics) {
+        for (counter, &count) in metrics.instructions.iter().zip(&self.get_counters().0) {
+            counter.absolute(count);
+        }
+        let memory = self.memory_stats();
+        metrics.memory_words_current.set(memory.current_words as f64);
+        metrics.memory_words_peak.set(memory.peak_words as f64);
+        let arrays = self.alloc_stats();
+        metrics.arrays_allocated.absolute(arrays.alloc_count);
+        metrics.arrays_freed.absolute(arrays.free_count);
+        metrics.arrays_live.set(arrays.live_arrays as f64);
+    }
+}
+
+#[test]
+fn publishes_machine_counts() {
+    use std::{collections::BTreeMap, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+    use ::metrics::{Key, KeyName, Metadata, Recorder, SharedString, Unit, Histogram};
+
+    use crate::{testing::NullIO, um::program_bytes};
+
+    // Keeps the latest value of every metric by `name{label=value}`.
+    #[derive(Default)]
+    struct Values(Mutex<BTreeMap<String, Arc<AtomicU64>>>);
+
+    impl Values {
+        fn handle(&self, key: &Key) -> Arc<AtomicU64> {
+            let labels: String = key.labels().map(|l| format!("{{{}=\"{}\"}}", l.key(), l.value())).collect();
+            self.0.lock().unwrap().entry(format!("{}{}", key.name(), labels)).or_default().clone()
+        }
+
+        fn get(&self, name: &str) -> u64 {
+            self.0.lock().unwrap()[name].load(Ordering::Relaxed)
+        }
+    }
+
+    impl Recorder for Values {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+        fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.handle(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.handle(key))
+        }
+
+        fn register_histogram(&self, _key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    // r0 <- 3; r1 <- new[r0]; r2 <- new[r0]; free r1; halt
+    let program = program_bytes(&[0xD0000003, 0x80000008, 0x80000010, 0x90000001, 0x70000000]);
+    let mut um = UniversalMachine::new(&program, NullIO).unwrap();
+    um.run();
+    let values = Values::default();
+    let metrics = ::metrics::with_local_recorder(&values, Metrics::new);
+    um.record_metrics(&metrics);
+    assert_eq!(values.get("um_instructions_total{opcode=\"alloc\"}"), 2);
+    assert_eq!(values.get("um_instructions_total{opcode=\"store_const\"}"), 1);
+    assert_eq!(values.get("um_arrays_allocated_total"), 2);
+    assert_eq!(values.get("um_arrays_freed_total"), 1);
+    // Gauges store the bits of an `f64`.
+    assert_eq!(f64::from_bits(values.get("um_arrays_live")), 1.0);
+    assert_eq!(f64::from_bits(values.get("um_memory_words_current")), 8.0);
+    assert_eq!(f64::from_bits(values.get("um_memory_words_peak")), 11.0);
+}
@@ -0,0 +1,166 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::um::{Plate, UmError, UniversalMachine};
+
+/// Instructions each machine runs per turn unless set with `MultiMachine::with_quantum`.
+pub const DEFAULT_QUANTUM: u64 = 1000;
+
+/// Runs several machines in turns of `quantum` instructions each, in the order they
+/// were added, and moves arrays between them.
+///
+/// An array belongs to one machine at a time. `share_array` moves it from one machine
+/// to another; `deposit` moves it into the scheduler's pool, from where `withdraw`
+/// hands it to any machine later. Moving frees the array in the sender as `Free`
+/// would, so the sending program must not use its id afterwards. The machines' own
+/// `step_limit`s are ignored.
+pub struct MultiMachine<'a> {
+    pub machines: Vec<UniversalMachine<'a>>,
+    // Indexed by pool id; `None` once withdrawn.
+    pool: Vec<Option<Arc<[Plate]>>>,
+    quantum: u64,
+}
+
+impl <'a> MultiMachine<'a> {
+    pub fn new() -> Self {
+        MultiMachine::with_quantum(DEFAULT_QUANTUM)
+    }
+
+    pub fn with_quantum(quantum: u64) -> Self {
+        assert!(quantum > 0, "machines need at least one instruction per turn");
+        MultiMachine { machines: Vec::new(), pool: Vec::new(), quantum }
+    }
+
+    /// Returns the index to refer to `machine` by.
+    pub fn add(&mut self, machine: UniversalMachine<'a>) -> usize {
+        self.machines.push(machine);
+        self.machines.len() - 1
+    }
+
+    /// Moves the array machine `from` knows as `array_id` to machine `to`, returning
+    /// the id it has there. Both ids are as the programs see them; getting the new id
+    /// to the receiving program, e.g. through its registers, is up to the caller. On
+    /// error neither machine is changed.
+    pub fn share_array(&mut self, from: usize, to: usize, array_id: Plate) -> Result<Plate, UmError> {
+        let array = self.movable_array(from, array_id)?;
+        let id = self.machines[to].adopt_array(array)?;
+        self.machines[from].take_array(array_id);
+        Ok(id)
+    }
+
+    /// Moves the array machine `from` knows as `array_id` into the pool and returns its
+    /// pool id.
+    pub fn deposit(&mut self, from: usize, array_id: Plate) -> Result<Plate, UmError> {
+        self.movable_array(from, array_id)?;
+        let array = self.machines[from].take_array(array_id);
+        match self.pool.iter().position(Option::is_none) {
+            Some(pool_id) => {
+                self.pool[pool_id] = array;
+                Ok(pool_id as Plate)
+            },
+            None => {
+                self.pool.push(array);
+                Ok(self.pool.len() as Plate - 1)
+            },
+        }
+    }
+
+    /// Moves the array with `pool_id` out of the pool into machine `to`, returning the
+    /// id it has there. On error the array stays in the pool.
+    pub fn withdraw(&mut self, pool_id: Plate, to: usize) -> Result<Plate, UmError> {
+        let array = self.pooled_array(pool_id)
+            .ok_or(UmError::InvalidArrayAccess { id: pool_id, offset: 0 })?
+            .clone();
+        let id = self.machines[to].adopt_array(array)?;
+        self.pool[pool_id as usize] = None;
+        Ok(id)
+    }
+
+    /// Contents of the array with `pool_id`, if it is still in the pool.
+    pub fn pooled_array(&self, pool_id: Plate) -> Option<&Arc<[Plate]>> {
+        self.pool.get(pool_id as usize)?.as_ref()
+    }
+
+    /// Array 0 holds the program and cannot be moved.
+    fn movable_array(&self, from: usize, array_id: Plate) -> Result<Arc<[Plate]>, UmError> {
+        let invalid = UmError::InvalidArrayAccess { id: array_id, offset: 0 };
+        if array_id == 0 {
+            return Err(invalid);
+        }
+        self.machines[from].shared_array(array_id).ok_or(invalid)
+    }
+
+    /// Gives every running machine one turn. Returns `true` while any is still running.
+    pub fn run_round(&mut self) -> bool {
+        let mut running = false;
+        for machine in &mut self.machines {
+            for _ in 0..self.quantum {
+                if !machine.step() {
+                    break;
+                }
+            }
+            running |= !machine.is_halted;
+        }
+        running
+    }
+
+    /// Runs until every machine has halted.
+    pub fn run(&mut self) {
+        while self.run_round() {}
+    }
+}
+
+impl Default for MultiMachine<'_> {
+    fn default() -> Self {
+        MultiMachine::new()
+    }
+}
+
+#[test]
+fn passes_arrays_between_machines() {
+    use crate::{testing::{NullIO, RecordingIO}, um::program_bytes};
+
+    // r0 <- 1; r1 <- new[r0]; r2 <- 'm'; r3 <- 0; [r1][r3] <- r2; halt
+    let sender = program_bytes(&[0xD0000001, 0x80000008, 0xD400006D, 0xD6000000, 0x2000005A, 0x70000000]);
+    // r3 <- 0; r0 <- [r1][r3]; out r0; halt, where r1 is set by the host
+    let receiver = program_bytes(&[0xD6000000, 0x1000000B, 0xA0000000, 0x70000000]);
+    let mut output = RecordingIO::new(&[]);
+    let mut multi = MultiMachine::with_quantum(2);
+    let a = multi.add(UniversalMachine::new(&sender, NullIO).unwrap());
+    let b = multi.add(UniversalMachine::new(&sender, NullIO).unwrap());
+    assert!(multi.run_round());
+    assert_eq!((multi.machines[a].steps_executed(), multi.machines[b].steps_executed()), (2, 2));
+    multi.run();
+    assert!(multi.machines[a].is_halted);
+
+    let c = multi.add(UniversalMachine::new(&receiver, &mut output).unwrap());
+    assert_eq!(multi.share_array(a, c, 5), Err(UmError::InvalidArrayAccess { id: 5, offset: 0 }));
+    assert_eq!(multi.share_array(a, c, 0), Err(UmError::InvalidArrayAccess { id: 0, offset: 0 }));
+    let sent = multi.machines[a].registers[1];
+    let id = multi.share_array(a, c, sent).unwrap();
+    assert!(!multi.machines[a].is_array_allocated(sent));
+    assert_eq!(multi.machines[a].alloc_stats().live_arrays, 0);
+    multi.machines[c].registers[1] = id;
+    multi.run();
+    assert_eq!(multi.machines[c].alloc_stats().live_arrays, 1);
+    drop(multi);
+    assert_eq!(output.output_bytes(), b"m");
+}
+
+#[test]
+fn moves_arrays_through_the_pool() {
+    use crate::testing::NullIO;
+
+    let mut multi = MultiMachine::new();
+    let a = multi.add(UniversalMachine::default());
+    let b = multi.add(UniversalMachine::builder(NullIO).program(&[0x70, 0, 0, 0]).memory_limit_words(2).build().unwrap());
+    let id = multi.machines[a].alloc_with_content(vec![1, 2, 3]).unwrap();
+    let pool_id = multi.deposit(a, id).unwrap();
+    assert_eq!(multi.deposit(a, id), Err(UmError::InvalidArrayAccess { id, offset: 0 }));
+    assert_eq!(multi.pooled_array(pool_id).map(|a| &a[..]), Some(&[1, 2, 3][..]));
+    assert!(matches!(multi.withdraw(pool_id, b), Err(UmError::MemoryLimitExceeded { .. })));
+    assert!(multi.pooled_array(pool_id).is_some());
+    let id = multi.withdraw(pool_id, a).unwrap();
+    assert_eq!(multi.machines[a].read_array(id, 2), Some(3));
+    assert_eq!(multi.pooled_array(pool_id), None);
+    assert_eq!(multi.withdraw(pool_id, a), Err(UmError::InvalidArrayAccess { id: pool_id, offset: 0 }));
+}
@@ -0,0 +1,312 @@
+use alloc::{boxed::Box, vec};
+use core::ops::Range;
+
+use crate::{io_types as io,
+            um::{plate_from_bytes, Command, IOInterface, Plate, Registers, RunOutcome, UmError}};
+
+/// Universal Machine whose memory is allocated once, when it is built: a slab of
+/// `MAX_WORDS` platters holding every array, and a table of `MAX_ARRAYS` ids
+/// including array 0. Running it never allocates.
+///
+/// `Alloc` bump-allocates from the slab and takes the lowest free id. `Free` only
+/// returns words to the slab when the freed array is the last one in it, so programs
+/// that free in allocation order keep fragmenting the slab until it runs out. Running
+/// out of words or ids, accessing a missing array or an offset outside of one, and
+/// dividing by zero halt the machine with the corresponding `UmError` instead of
+/// panicking.
+pub struct StaticMachine<I: IOInterface, const MAX_ARRAYS: usize, const MAX_WORDS: usize> {
+    pub registers: Registers,
+    pub ip: usize,
+    pub is_halted: bool,
+    io: I,
+    slab: Box<[Plate]>,
+    // End of the highest array in `slab`.
+    top: usize,
+    arrays: [Option<Range<usize>>; MAX_ARRAYS],
+    halt_reason: Option<UmError>,
+    io_error: Option<io::Error>,
+}
+
+impl <I: IOInterface, const MAX_ARRAYS: usize, const MAX_WORDS: usize> StaticMachine<I, MAX_ARRAYS, MAX_WORDS> {
+    /// Fails with `UmError::MemoryLimitExceeded` if the program alone does not fit
+    /// into `MAX_WORDS`.
+    pub fn new(program: &[u8], io: I) -> Result<Self, UmError> {
+        assert!(MAX_ARRAYS > 0, "array 0 needs a slot");
+        let len = program.len().div_ceil(4);
+        if len > MAX_WORDS {
+            return Err(UmError::MemoryLimitExceeded { requested: len as u64, limit: MAX_WORDS as u64 });
+        }
+        let mut slab = vec![0; MAX_WORDS].into_boxed_slice();
+        for (i, chunk) in program.chunks(4).enumerate() {
+            slab[i] = plate_from_bytes(chunk).ok_or(UmError::MalformedProgram { byte_offset: i * 4 })?;
+        }
+        let mut arrays = [const { None }; MAX_ARRAYS];
+        arrays[0] = Some(0..len);
+        Ok(StaticMachine {
+            registers: Registers::default(),
+            ip: 0,
+            is_halted: false,
+            io,
+            slab,
+            top: len,
+            arrays,
+            halt_reason: None,
+            io_error: None,
+        })
+    }
+
+    pub fn halt_reason(&self) -> Option<&UmError> {
+        self.halt_reason.as_ref()
+    }
+
+    pub fn array(&self, id: Plate) -> Option<&[Plate]> {
+        let range = self.arrays.get(id as usize)?.clone()?;
+        Some(&self.slab[range])
+    }
+
+    /// Words left for `Alloc` at the end of the slab.
+    pub fn free_words(&self) -> usize {
+        MAX_WORDS - self.top
+    }
+
+    /// Runs until the machine halts.
+    pub fn run(&mut self) -> RunOutcome {
+        while self.step() {}
+        match self.io_error.take() {
+            Some(e) => RunOutcome::IOError(e),
+            None => RunOutcome::Halted,
+        }
+    }
+
+    /// Executes one instruction. Returns `true` while the machine is still running.
+    pub fn step(&mut self) -> bool {
+        if self.is_halted {
+            return false;
+        }
+        let program = self.arrays[0].clone().unwrap();
+        if self.ip >= program.len() {
+            self.halt_with(UmError::InvalidInstructionPointer { requested: self.ip, array0_len: program.len() });
+            return false;
+        }
+        let command = Command::decode(self.slab[program.start + self.ip]);
+        self.perform_command(command);
+        if !matches!(command, Command::LoadProg { .. }) {
+            self.ip += 1;
+        }
+        !self.is_halted
+    }
+
+    fn perform_command(&mut self, command: Command) {
+        match command {
+            Command::CondMove { dst, src, cnd } => {
+                if self.registers[cnd] != 0 {
+                    self.registers[dst] = self.registers[src];
+                }
+            },
+            Command::ArrLoad { dst, arr, offset } => {
+                if let Some(cell) = self.cell(self.registers[arr], self.registers[offset]) {
+                    self.registers[dst] = self.slab[cell];
+                }
+            },
+            Command::ArrStore { src, arr, offset } => {
+                if let Some(cell) = self.cell(self.registers[arr], self.registers[offset]) {
+                    self.slab[cell] = self.registers[src];
+                }
+            },
+            Command::Add { dst, op1, op2 } => {
+                self.registers[dst] = self.registers[op1].wrapping_add(self.registers[op2]);
+            },
+            Command::Mul { dst, op1, op2 } => {
+                self.registers[dst] = self.registers[op1].wrapping_mul(self.registers[op2]);
+            },
+            Command::Div { dst, op1, op2 } => {
+                match self.registers[op1].checked_div(self.registers[op2]) {
+                    Some(v) => self.registers[dst] = v,
+                    None => self.halt_with(UmError::DivisionByZero { ip: self.ip }),
+                }
+            },
+            Command::NotAnd { dst, op1, op2 } => {
+                self.registers[dst] = !(self.registers[op1] & self.registers[op2]);
+            },
+            Command::Halt => self.halt(),
+            Command::Alloc { dst, size } => {
+                let size = self.registers[size] as usize;
+                if let Some(id) = self.alloc(size) {
+                    self.slab[self.arrays[id].clone().unwrap()].fill(0);
+                    self.registers[dst] = id as Plate;
+                }
+            },
+            Command::Free { arr } => {
+                let id = self.registers[arr] as usize;
+                match self.arrays.get_mut(id).filter(|_| id != 0).and_then(Option::take) {
+                    Some(range) => self.release(range),
+                    None => self.halt_with(UmError::InvalidArrayAccess { id: id as Plate, offset: 0 }),
+                }
+            },
+            Command::Output { src } => {
+                if let Err(e) = self.io.request_output(self.registers[src] as u8) {
+                    self.fail_io(e);
+                }
+            },
+            Command::Input { dst } => {
+                match self.io.request_input() {
+                    Ok(ch) => self.registers[dst] = ch as Plate,
+                    Err(e) => self.fail_io(e),
+                }
+            },
+            Command::LoadProg { arr, offset } => {
+                let id = self.registers[arr];
+                if id != 0 && !self.load_program(id) {
+                    return;
+                }
+                self.ip = self.registers[offset] as usize;
+            },
+            Command::StoreConst { dst, val } => {
+                self.registers[dst] = val;
+            },
+            Command::Invalid(raw) => {
+                self.halt_with(UmError::InvalidOpcode { raw, ip: self.ip });
+            },
+        }
+    }
+
+    /// Slab index of `offset` in array `id`, or `None` after halting the machine.
+    fn cell(&mut self, id: Plate, offset: Plate) -> Option<usize> {
+        let offset = offset as usize;
+        match self.arrays.get(id as usize) {
+            Some(Some(range)) if offset < range.len() => Some(range.start + offset),
+            _ => {
+                self.halt_with(UmError::InvalidArrayAccess { id, offset });
+                None
+            },
+        }
+    }
+
+    /// Takes the lowest free id and `size` words from the top of the slab, or halts
+    /// the machine if either has run out.
+    fn alloc(&mut self, size: usize) -> Option<usize> {
+        let Some(id) = self.arrays.iter().position(Option::is_none) else {
+            self.halt_with(UmError::ArrayIdExhausted);
+            return None;
+        };
+        if size > MAX_WORDS - self.top {
+            let requested = (self.top + size) as u64;
+            self.halt_with(UmError::MemoryLimitExceeded { requested, limit: MAX_WORDS as u64 });
+            return None;
+        }
+        self.arrays[id] = Some(self.top..self.top + size);
+        self.top += size;
+        Some(id)
+    }
+
+    fn release(&mut self, range: Range<usize>) {
+        if range.end == self.top {
+            self.top = range.start;
+        }
+    }
+
+    /// Replaces array 0 with a copy of array `id`; returns `false` after halting the
+    /// machine if that fails.
+    fn load_program(&mut self, id: Plate) -> bool {
+        let Some(Some(source)) = self.arrays.get(id as usize).cloned() else {
+            self.halt_with(UmError::InvalidArrayAccess { id, offset: 0 });
+            return false;
+        };
+        // Array 0 is dropped first so that its words can be reused for the copy.
+        let old = self.arrays[0].take().unwrap();
+        let top = self.top;
+        self.release(old.clone());
+        match self.alloc(source.len()) {
+            Some(new) => {
+                let start = self.arrays[new].take().unwrap().start;
+                self.slab.copy_within(source.clone(), start);
+                self.arrays[0] = Some(start..start + source.len());
+                true
+            },
+            None => {
+                // The machine is halted, but keep array 0 around for inspection.
+                self.arrays[0] = Some(old);
+                self.top = top;
+                false
+            },
+        }
+    }
+
+    fn halt_with(&mut self, reason: UmError) {
+        self.halt_reason = Some(reason);
+        self.halt();
+    }
+
+    fn fail_io(&mut self, error: io::Error) {
+        let reason = UmError::IoFailed { ip: self.ip, kind: error.kind() };
+        self.io_error = Some(error);
+        self.halt_with(reason);
+    }
+
+    fn halt(&mut self) {
+        self.is_halted = true;
+        if let Err(e) = self.io.flush() {
+            self.halt_reason.get_or_insert(UmError::IoFailed { ip: self.ip, kind: e.kind() });
+            self.io_error.get_or_insert(e);
+        }
+    }
+}
+
+#[test]
+fn runs_within_fixed_memory() {
+    use crate::{testing::RecordingIO, um::program_bytes};
+
+    // r0 <- 3; r1 <- new[r0]; r2 <- 'k'; r4 <- 2; [r1][r4] <- r2; r5 <- [r1][r4]; out r5; free r1; halt
+    let program = program_bytes(&[0xD0000003, 0x80000008, 0xD400006B, 0xD8000002, 0x20000062,
+                                  0x1000014C, 0xA0000005, 0x90000001, 0x70000000]);
+    let mut io = RecordingIO::new(&[]);
+    let mut um = StaticMachine::<_, 4, 16>::new(&program, &mut io).unwrap();
+    assert_eq!(um.free_words(), 7);
+    assert!(matches!(um.run(), RunOutcome::Halted));
+    assert_eq!(um.halt_reason(), None);
+    assert_eq!(um.array(1), None);
+    assert_eq!(um.free_words(), 7);
+    drop(um);
+    assert_eq!(io.output_bytes(), b"k");
+
+    let mut um = StaticMachine::<_, 4, 11>::new(&program, RecordingIO::new(&[])).unwrap();
+    um.run();
+    assert_eq!(um.halt_reason(), Some(&UmError::MemoryLimitExceeded { requested: 12, limit: 11 }));
+    let mut um = StaticMachine::<_, 1, 16>::new(&program, RecordingIO::new(&[])).unwrap();
+    um.run();
+    assert_eq!(um.halt_reason(), Some(&UmError::ArrayIdExhausted));
+}
+
+#[test]
+fn load_prog_replaces_array_0() {
+    use crate::{testing::NullIO, um::program_bytes};
+
+    // r0 <- 2; r1 <- new[r0]; r2 <- 0x7000; r5 <- 0x10000; r2 <- r2 * r5 (HALT); r3 <- 0;
+    // [r1][r3] <- r2; load_prog r1, r3
+    let program = program_bytes(&[0xD0000002, 0x80000008, 0xD4007000, 0xDA010000, 0x40000095,
+                                  0xD6000000, 0x2000005A, 0xC000000B]);
+    let mut um = StaticMachine::<_, 2, 12>::new(&program, NullIO).unwrap();
+    assert!(matches!(um.run(), RunOutcome::Halted));
+    assert_eq!(um.halt_reason(), None);
+    assert_eq!(um.ip, 1);
+    assert_eq!(um.array(0), um.array(1));
+    assert_eq!(um.free_words(), 0);
+}
+
+#[test]
+fn failed_load_prog_restores_slab_top() {
+    use crate::testing::{test_program, NullIO};
+
+    // Moves array 0 to the top of the slab as a single LOAD_PROG r1, r5, which then
+    // fails to copy the three words of array 1 into the space left.
+    let program = test_program![
+        StoreConst r0 = 3; Alloc r1, r0; StoreConst r2 = 1; Alloc r3, r2;
+        StoreConst r4 = 0xC000; StoreConst r6 = 0x10000; Mul r4, r4, r6; StoreConst r7 = 0xD; Add r4, r4, r7;
+        ArrStore r3, r5, r4; LoadProg r3, r5
+    ];
+    let mut um = StaticMachine::<_, 4, 16>::new(&program, NullIO).unwrap();
+    um.run();
+    assert_eq!(um.halt_reason(), Some(&UmError::MemoryLimitExceeded { requested: 18, limit: 16 }));
+    assert_eq!(um.array(0), Some(&[0xC000000D][..]));
+    assert_eq!(um.free_words(), 0);
+}
@@ -0,0 +1,27 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::um::Plate;
+
+/// Reads a string stored one byte per platter in the low byte of each word, up to the
+/// first zero platter. Bytes that do not form valid UTF-8 become U+FFFD.
+pub fn array_to_string(arr: &[Plate]) -> String {
+    let bytes: Vec<u8> = arr.iter()
+        .take_while(|&&p| p != 0)
+        .map(|&p| p as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Encodes `s` as one UTF-8 byte per platter. No terminating zero is appended.
+pub fn string_to_array(s: &str) -> Vec<Plate> {
+    s.bytes().map(Plate::from).collect()
+}
+
+#[test]
+fn round_trips_strings() {
+    let mut arr = string_to_array("bound var");
+    assert_eq!(&arr[..5], &[0x62, 0x6F, 0x75, 0x6E, 0x64]);
+    arr.extend([0, 0x21]);
+    assert_eq!(array_to_string(&arr), "bound var");
+    assert_eq!(array_to_string(&[0x1FF, 0x41]), "\u{FFFD}A");
+}
@@ -0,0 +1,325 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::fmt;
+
+use crate::{io_types as io, um::IOInterface};
+
+/// Console that reports end of input on every read and discards all output.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullIO;
+
+impl IOInterface for NullIO {
+    fn request_input(&mut self) -> io::Result<u8> {
+        Ok(0xFF)
+    }
+
+    fn request_output(&mut self, _ch: u8) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Console that serves queued input and captures everything the program writes.
+/// Once the input runs out, reads return `0xFF`.
+///
+/// ```
+/// use cult_of_the_bound_var::{testing::RecordingIO, um::UniversalMachine};
+///
+/// // in r0; out r0; halt
+/// let program = [0xB0, 0, 0, 0, 0xA0, 0, 0, 0, 0x70, 0, 0, 0];
+/// let mut io = RecordingIO::new(b"!");
+/// UniversalMachine::new(&program, &mut io).unwrap().run();
+/// assert_eq!(io.output_bytes(), b"!");
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct RecordingIO {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl RecordingIO {
+    pub fn new(input: &[u8]) -> Self {
+        RecordingIO {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn output_bytes(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Replaces whatever input has not been read yet.
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.input = input.iter().copied().collect();
+    }
+
+    /// Appends `bytes` after any input not read yet.
+    pub fn inject_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// Appends `line` and a newline, one byte per character in Latin-1; characters
+    /// beyond U+00FF become `?`.
+    pub fn inject_input_line(&mut self, line: &str) {
+        self.input.extend(line.chars().map(|c| u8::try_from(c).unwrap_or(b'?')));
+        self.input.push_back(b'\n');
+    }
+
+    /// Returns the output captured so far, leaving the buffer empty.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.output)
+    }
+}
+
+impl IOInterface for RecordingIO {
+    fn request_input(&mut self) -> io::Result<u8> {
+        Ok(self.input.pop_front().unwrap_or(0xFF))
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.output.push(ch);
+        Ok(())
+    }
+}
+
+/// Register number of an operand written `rN` in `test_program!`.
+#[cfg(test)]
+pub(crate) fn reg(operand: &str) -> crate::um::RegId {
+    operand.strip_prefix('r')
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n < 8)
+        .unwrap_or_else(|| panic!("`{}` is not a register", operand))
+}
+
+/// Program image built from instructions named after `Command`'s variants, with
+/// operands in the order of their `Display` forms:
+/// `test_program![StoreConst r0 = 42; StoreConst r1 = 0; Add r2, r0, r1; Halt]`.
+#[cfg(test)]
+macro_rules! test_program {
+    ($($op:ident $($operand:ident),* $(= $val:expr)?);* $(;)?) => {
+        $crate::um::program_bytes(&[$(test_program!(@command $op [$($operand)*] [$($val)?]).encode()),*])
+    };
+    (@command CondMove [$dst:ident $src:ident $cnd:ident] []) => {
+        $crate::um::Command::CondMove { dst: test_program!(@reg $dst), src: test_program!(@reg $src),
+                                        cnd: test_program!(@reg $cnd) }
+    };
+    (@command ArrLoad [$dst:ident $arr:ident $offset:ident] []) => {
+        $crate::um::Command::ArrLoad { dst: test_program!(@reg $dst), arr: test_program!(@reg $arr),
+                                       offset: test_program!(@reg $offset) }
+    };
+    (@command ArrStore [$arr:ident $offset:ident $src:ident] []) => {
+        $crate::um::Command::ArrStore { arr: test_program!(@reg $arr), offset: test_program!(@reg $offset),
+                                        src: test_program!(@reg $src) }
+    };
+    (@command $op:ident [$dst:ident $op1:ident $op2:ident] []) => {
+        $crate::um::Command::$op { dst: test_program!(@reg $dst), op1: test_program!(@reg $op1),
+                                   op2: test_program!(@reg $op2) }
+    };
+    (@command Halt [] []) => {
+        $crate::um::Command::Halt
+    };
+    (@command Alloc [$dst:ident $size:ident] []) => {
+        $crate::um::Command::Alloc { dst: test_program!(@reg $dst), size: test_program!(@reg $size) }
+    };
+    (@command Free [$arr:ident] []) => {
+        $crate::um::Command::Free { arr: test_program!(@reg $arr) }
+    };
+    (@command Output [$src:ident] []) => {
+        $crate::um::Command::Output { src: test_program!(@reg $src) }
+    };
+    (@command Input [$dst:ident] []) => {
+        $crate::um::Command::Input { dst: test_program!(@reg $dst) }
+    };
+    (@command LoadProg [$arr:ident $offset:ident] []) => {
+        $crate::um::Command::LoadProg { arr: test_program!(@reg $arr), offset: test_program!(@reg $offset) }
+    };
+    (@command StoreConst [$dst:ident] [$val:expr]) => {
+        $crate::um::Command::StoreConst { dst: test_program!(@reg $dst), val: $val }
+    };
+    (@reg $operand:ident) => {
+        $crate::testing::reg(stringify!($operand))
+    };
+}
+
+#[cfg(test)]
+pub(crate) use test_program;
+
+/// One exchange in a `ScriptedIO` conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptStep {
+    /// The program must write exactly these bytes next.
+    Expect(Vec<u8>),
+    /// Served to the program's next reads.
+    Send(Vec<u8>),
+}
+
+/// Where a program left its `ScriptedIO` script; `step` indexes the script. For an
+/// `Expect` step, `got` is what the program wrote for it, up to and including the
+/// first wrong byte. For a `Send` step, `expected` is the input left unread, or is
+/// empty if the program wrote `got` instead of reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub expected: Vec<u8>,
+    pub got: Vec<u8>,
+    pub step: usize,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "script step {}: expected {:?}, got {:?}", self.step,
+               self.expected.escape_ascii(), self.got.escape_ascii())
+    }
+}
+
+impl core::error::Error for ScriptError {}
+
+/// Console that checks a program against a conversation script, in the spirit of
+/// `expect`. Output must match the current `Expect` step byte for byte, and input is
+/// only served from `Send` steps; reading while output is still expected, or writing
+/// something else, fails the request and keeps the `ScriptError` for `error` and
+/// `finish`. Reads past the end of the script return `0xFF`.
+///
+/// ```
+/// use cult_of_the_bound_var::{testing::{ScriptedIO, ScriptStep}, um::UniversalMachine};
+///
+/// // in r0; out r0; halt
+/// let program = [0xB0, 0, 0, 0, 0xA0, 0, 0, 0, 0x70, 0, 0, 0];
+/// let mut io = ScriptedIO::new(vec![ScriptStep::Send(b"!".to_vec()), ScriptStep::Expect(b"!".to_vec())]);
+/// UniversalMachine::new(&program, &mut io).unwrap().run();
+/// assert_eq!(io.finish(), Ok(()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScriptedIO {
+    script: Vec<ScriptStep>,
+    step: usize,
+    // Bytes of the current step already written or served.
+    done: usize,
+    error: Option<ScriptError>,
+}
+
+impl ScriptedIO {
+    pub fn new(script: Vec<ScriptStep>) -> Self {
+        let mut io = ScriptedIO { script, step: 0, done: 0, error: None };
+        io.skip_finished();
+        io
+    }
+
+    pub fn error(&self) -> Option<&ScriptError> {
+        self.error.as_ref()
+    }
+
+    /// Succeeds if the program followed the whole script.
+    pub fn finish(self) -> Result<(), ScriptError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.script.get(self.step) {
+            Some(ScriptStep::Expect(expected)) => Err(ScriptError {
+                expected: expected.clone(),
+                got: expected[..self.done].to_vec(),
+                step: self.step,
+            }),
+            Some(ScriptStep::Send(sent)) => Err(ScriptError {
+                expected: sent[self.done..].to_vec(),
+                got: Vec::new(),
+                step: self.step,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn skip_finished(&mut self) {
+        while let Some(ScriptStep::Expect(bytes) | ScriptStep::Send(bytes)) = self.script.get(self.step) {
+            if self.done < bytes.len() {
+                break;
+            }
+            self.step += 1;
+            self.done = 0;
+        }
+    }
+
+    fn fail(&mut self, expected: &[u8], got: Vec<u8>) -> io::Error {
+        self.error = Some(ScriptError { expected: expected.to_vec(), got, step: self.step });
+        io::ErrorKind::Other.into()
+    }
+}
+
+impl IOInterface for ScriptedIO {
+    fn request_input(&mut self) -> io::Result<u8> {
+        if self.error.is_some() {
+            return Err(io::ErrorKind::Other.into());
+        }
+        let byte = match self.script.get(self.step) {
+            Some(ScriptStep::Send(bytes)) => bytes[self.done],
+            Some(ScriptStep::Expect(bytes)) => {
+                let (expected, got) = (bytes.clone(), bytes[..self.done].to_vec());
+                return Err(self.fail(&expected, got));
+            },
+            None => return Ok(0xFF),
+        };
+        self.done += 1;
+        self.skip_finished();
+        Ok(byte)
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        if self.error.is_some() {
+            return Err(io::ErrorKind::Other.into());
+        }
+        match self.script.get(self.step) {
+            Some(ScriptStep::Expect(bytes)) if bytes[self.done] == ch => {},
+            Some(ScriptStep::Expect(bytes)) => {
+                let (expected, mut got) = (bytes.clone(), bytes[..self.done].to_vec());
+                got.push(ch);
+                return Err(self.fail(&expected, got));
+            },
+            _ => return Err(self.fail(&[], alloc::vec![ch])),
+        }
+        self.done += 1;
+        self.skip_finished();
+        Ok(())
+    }
+}
+
+#[test]
+fn injects_input_lines() {
+    let mut io = RecordingIO::new(b"a");
+    io.inject_input(b"b");
+    io.inject_input_line("\u{e9}\u{2603}");
+    let input: Vec<u8> = (0..5).map(|_| io.request_input().unwrap()).collect();
+    assert_eq!(input, [b'a', b'b', 0xE9, b'?', b'\n']);
+    assert_eq!(io.request_input().unwrap(), 0xFF);
+}
+
+#[test]
+fn scripted_io_checks_conversation() {
+    use crate::um::{program_bytes, RunOutcome, UniversalMachine};
+
+    // in r0; in r1; out r0; out r1; halt
+    let program = program_bytes(&[0xB0000000, 0xB0000001, 0xA0000000, 0xA0000001, 0x70000000]);
+    let run = |script| {
+        let mut io = ScriptedIO::new(script);
+        let outcome = UniversalMachine::new(&program, &mut io).unwrap().run();
+        (outcome, io)
+    };
+    let send = |bytes: &[u8]| ScriptStep::Send(bytes.to_vec());
+    let expect = |bytes: &[u8]| ScriptStep::Expect(bytes.to_vec());
+
+    let (_, io) = run(alloc::vec![send(b"ab"), expect(b"ab")]);
+    assert_eq!(io.finish(), Ok(()));
+
+    let (outcome, io) = run(alloc::vec![send(b"ab"), expect(b"ax")]);
+    assert!(matches!(outcome, RunOutcome::IOError(_)));
+    assert_eq!(io.error(), Some(&ScriptError { expected: b"ax".to_vec(), got: b"ab".to_vec(), step: 1 }));
+
+    let (_, io) = run(alloc::vec![send(b"a"), expect(b"a")]);
+    assert_eq!(io.finish(), Err(ScriptError { expected: b"a".to_vec(), got: Vec::new(), step: 1 }));
+
+    let (_, io) = run(alloc::vec![send(b"ab"), expect(b"ab"), send(b"c")]);
+    assert_eq!(io.finish(), Err(ScriptError { expected: b"c".to_vec(), got: Vec::new(), step: 2 }));
+}
+
+#[test]
+fn test_program_matches_handwritten_platters() {
+    let program = test_program![StoreConst r0 = 42; StoreConst r1 = 0; Add r2, r0, r1; ArrStore r1, r2, r3; Halt];
+    assert_eq!(program, crate::um::program_bytes(&[0xD000002A, 0xD2000000, 0x30000081, 0x20000053, 0x70000000]));
+}
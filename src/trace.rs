@@ -0,0 +1,121 @@
+use alloc::{collections::VecDeque, format, string::{String, ToString}, vec::Vec};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::um::{Command, Plate};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub step: u64,
+    pub ip: usize,
+    pub command: Command,
+    pub registers_before: [Plate; 8],
+}
+
+impl TraceEntry {
+    /// Renders the entry as a single line of JSON, without the trailing newline.
+    pub fn to_json(&self) -> String {
+        let registers: Vec<String> = self.registers_before.iter().map(|r| r.to_string()).collect();
+        format!("{{\"step\":{},\"ip\":{},\"command\":\"{}\",\"registers_before\":[{}]}}",
+                self.step, self.ip, self.command, registers.join(","))
+    }
+}
+
+enum Sink {
+    Memory {
+        capacity: usize,
+        entries: VecDeque<TraceEntry>,
+    },
+    #[cfg(feature = "std")]
+    Writer {
+        writer: Box<dyn Write + Send>,
+        error: Option<io::Error>,
+    },
+}
+
+pub struct Tracer {
+    sink: Sink,
+    step: u64,
+}
+
+impl Tracer {
+    /// Keeps the last `capacity` entries in memory, dropping older ones.
+    pub fn push_to_vec(capacity: usize) -> Self {
+        Tracer {
+            sink: Sink::Memory { capacity, entries: VecDeque::with_capacity(capacity) },
+            step: 0,
+        }
+    }
+
+    /// Streams every entry to `writer` as a line of JSON. Writing stops at the first
+    /// IO error, which is then available through `error`.
+    #[cfg(feature = "std")]
+    pub fn push_to_writer(writer: Box<dyn Write + Send>) -> Self {
+        Tracer {
+            sink: Sink::Writer { writer, error: None },
+            step: 0,
+        }
+    }
+
+    pub fn record(&mut self, ip: usize, command: Command, registers_before: [Plate; 8]) {
+        let entry = TraceEntry { step: self.step, ip, command, registers_before };
+        self.step += 1;
+        match &mut self.sink {
+            Sink::Memory { capacity, entries } => {
+                if *capacity == 0 {
+                    return;
+                }
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            },
+            #[cfg(feature = "std")]
+            Sink::Writer { writer, error } => {
+                if error.is_none() {
+                    if let Err(e) = writeln!(writer, "{}", entry.to_json()) {
+                        *error = Some(e);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Entries held in memory, oldest first. Empty in writer mode.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        let entries = match &self.sink {
+            Sink::Memory { entries, .. } => Some(entries.iter()),
+            #[cfg(feature = "std")]
+            Sink::Writer { .. } => None,
+        };
+        entries.into_iter().flatten()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn error(&self) -> Option<&io::Error> {
+        match &self.sink {
+            Sink::Memory { .. } => None,
+            Sink::Writer { error, .. } => error.as_ref(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Memory { .. } => Ok(()),
+            Sink::Writer { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+#[test]
+fn ring_buffer_keeps_last_entries() {
+    let mut tracer = Tracer::push_to_vec(2);
+    for ip in 0..5 {
+        tracer.record(ip, Command::Halt, [0; 8]);
+    }
+    let steps: Vec<u64> = tracer.entries().map(|e| e.step).collect();
+    assert_eq!(steps, vec![3, 4]);
+    assert_eq!(tracer.entries().next().unwrap().to_json(),
+               r#"{"step":3,"ip":3,"command":"HALT","registers_before":[0,0,0,0,0,0,0,0]}"#);
+}
@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{io_types as io, testing::RecordingIO, um::{IOInterface, UniversalMachine}};
+
+/// Lets the handle reach the console while the machine owns it.
+struct SharedIO(Arc<Mutex<RecordingIO>>);
+
+impl IOInterface for SharedIO {
+    fn request_input(&mut self) -> io::Result<u8> {
+        self.0.lock().unwrap().request_input()
+    }
+
+    fn request_output(&mut self, ch: u8) -> io::Result<()> {
+        self.0.lock().unwrap().request_output(ch)
+    }
+}
+
+/// A machine for JavaScript callers. Input is queued with `set_input` and output is
+/// collected and returned by `run`.
+#[wasm_bindgen]
+pub struct UmHandle {
+    machine: UniversalMachine<'static>,
+    io: Arc<Mutex<RecordingIO>>,
+}
+
+#[wasm_bindgen]
+impl UmHandle {
+    #[wasm_bindgen(constructor)]
+    pub fn new(program: &[u8]) -> Result<UmHandle, JsError> {
+        let io = Arc::new(Mutex::new(RecordingIO::default()));
+        let machine = UniversalMachine::new(program, SharedIO(io.clone()))?;
+        Ok(UmHandle { machine, io })
+    }
+
+    /// Replaces any input the program has not read yet. Once it runs out, the
+    /// program reads end of input.
+    pub fn set_input(&mut self, s: &str) {
+        self.io.lock().unwrap().set_input(s.as_bytes());
+    }
+
+    /// Executes up to `max_steps` instructions and returns the output they produced,
+    /// with invalid UTF-8 replaced. Call again to continue until `is_halted`.
+    pub fn run(&mut self, max_steps: u32) -> String {
+        self.machine.step_limit = Some(max_steps.into());
+        self.machine.run();
+        let output = self.io.lock().unwrap().take_output();
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.machine.is_halted
+    }
+}
+
+#[test]
+fn runs_in_slices() {
+    use crate::um::program_bytes;
+
+    // in r0; out r0; out r0; halt
+    let program = program_bytes(&[0xB0000000, 0xA0000000, 0xA0000000, 0x70000000]);
+    let mut handle = UmHandle::new(&program).unwrap_or_else(|_| unreachable!());
+    handle.set_input("k");
+    assert_eq!(handle.run(2), "k");
+    assert!(!handle.is_halted());
+    assert_eq!(handle.run(10), "k");
+    assert!(handle.is_halted());
+}
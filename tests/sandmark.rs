@@ -0,0 +1,69 @@
+//! Runs the official sandmark to completion and checks everything it prints.
+#![cfg(feature = "std")]
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of sandmark's complete output, which ends in "SANDmark complete.".
+const EXPECTED_SHA256: &str = "b915fa2d4eb3e0ef2a5633fde1923a007ee54c55f7e97afd10745d76d6b66363";
+
+/// Bytes shown on either side of the first difference.
+const CONTEXT: usize = 80;
+
+/// Compares output against the known hash and, if it differs, against the reference
+/// transcript in `data/sandmark-output.txt` to point at where it went wrong.
+struct SandmarkVerifier {
+    expected_sha256: &'static str,
+}
+
+impl SandmarkVerifier {
+    fn verify(&self, output: &[u8]) -> Result<(), String> {
+        let actual = format!("{:x}", Sha256::digest(output));
+        if actual == self.expected_sha256 {
+            return Ok(());
+        }
+        let mut report = format!("output hash {} does not match {}", actual, self.expected_sha256);
+        let expected = std::fs::read("data/sandmark-output.txt").unwrap();
+        let position = output.iter().zip(&expected)
+            .position(|(a, b)| a != b)
+            .unwrap_or(output.len().min(expected.len()));
+        let context = |bytes: &[u8]| {
+            let end = bytes.len().min(position + CONTEXT);
+            String::from_utf8_lossy(&bytes[position.saturating_sub(CONTEXT)..end]).into_owned()
+        };
+        report += &format!("\nfirst difference at byte {} of {} (expected {})", position, output.len(), expected.len());
+        report += &format!("\nexpected: {:?}\n     got: {:?}", context(&expected), context(output));
+        Err(report)
+    }
+}
+
+// With 64-bit platters sandmark stops at its MUL check.
+#[cfg(not(feature = "wide-address"))]
+#[test]
+#[cfg_attr(debug_assertions, ignore = "takes minutes without optimisations, run with --release")]
+fn sandmark_output_matches() {
+    use cult_of_the_bound_var::{testing::RecordingIO, um::UniversalMachine};
+
+    let program = std::fs::read("data/sandmark.umz").unwrap();
+    let mut io = RecordingIO::new(&[]);
+    let mut um = UniversalMachine::new(&program, &mut io).unwrap();
+    um.run();
+    assert_eq!(um.halt_reason(), None);
+    drop(um);
+    let verifier = SandmarkVerifier { expected_sha256: EXPECTED_SHA256 };
+    if let Err(report) = verifier.verify(io.output_bytes()) {
+        panic!("{}", report);
+    }
+}
+
+#[test]
+fn reports_first_difference() {
+    let verifier = SandmarkVerifier { expected_sha256: EXPECTED_SHA256 };
+    let mut output = std::fs::read("data/sandmark-output.txt").unwrap();
+    assert_eq!(verifier.verify(&output), Ok(()));
+    output[100] ^= 1;
+    let report = verifier.verify(&output).unwrap_err();
+    assert!(report.contains("first difference at byte 100 of 2946"), "{}", report);
+    output.truncate(50);
+    let report = verifier.verify(&output).unwrap_err();
+    assert!(report.contains("first difference at byte 50 of 50"), "{}", report);
+}
@@ -0,0 +1,23 @@
+//! Machines are `Send`, so each can run on its own thread with its own IO. They are
+//! not `Sync`; see `IOInterface`.
+#![cfg(feature = "std")]
+
+use cult_of_the_bound_var::{testing::RecordingIO, um::UniversalMachine};
+
+#[test]
+fn machines_run_on_separate_threads() {
+    fn assert_send<T: Send>() {}
+    assert_send::<UniversalMachine<'static>>();
+
+    // in r0; out r0; halt
+    let program: Vec<u8> = [0xB0000000_u32, 0xA0000000, 0x70000000].iter().flat_map(|p| p.to_be_bytes()).collect();
+    let mut ios: Vec<RecordingIO> = (b'a'..=b'd').map(|ch| RecordingIO::new(&[ch])).collect();
+    std::thread::scope(|scope| {
+        for io in &mut ios {
+            let mut um = UniversalMachine::new(&program, io).unwrap();
+            scope.spawn(move || um.run());
+        }
+    });
+    let outputs: Vec<&[u8]> = ios.iter().map(|io| io.output_bytes()).collect();
+    assert_eq!(outputs, [b"a", b"b", b"c", b"d"]);
+}